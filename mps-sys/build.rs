@@ -11,26 +11,31 @@ use std::error::Error;
 
 use regex::Regex;
 
-fn generate_mps_args<P: AsRef<Path>>(header: P) -> Result<String, Box<Error>> {
+/// MPS headers for which bindings and keyword arguments are generated
+const MPS_HEADERS: &[&str] = &["mps.h", "mpstd.h", "mpsavm.h", "mpsacl.h", "mpscmfs.h", "mpscmvff.h", "mpscmvt.h", "mpscamc.h", "mpscams.h", "mpscawl.h", "mpsclo.h", "mpscsnc.h"];
+
+fn generate_mps_args<P: AsRef<Path>>(headers: &[P]) -> Result<String, Box<Error>> {
     let mut out = String::new();
     let re = Regex::new(r"^#define[\t ]+MPS_KEY_(?P<name>[A-Z_]+)_FIELD[\t ]+(?P<field>[a-z_]+)")?;
-    let source_code = BufReader::new(File::open(header)?);
 
     writeln!(&mut out, "#[macro_export] macro_rules! mps_arg_s {{")?;
-    for line in source_code.lines() {
-        let l = line?;
-        if let Some(c) = re.captures(&l) {
-            writeln!(
-                &mut out,
-                r"(MPS_KEY_{0}, $value:expr) => {{ unsafe {{
-                let mut _arg: $crate::mps_arg_s = ::std::mem::zeroed();
-                _arg.key = &$crate::_mps_key_{0};
-                _arg.val.{1} = $value;
-                _arg
-            }} }};",
-                &c["name"],
-                &c["field"]
-            )?;
+    for header in headers {
+        let source_code = BufReader::new(File::open(header)?);
+        for line in source_code.lines() {
+            let l = line?;
+            if let Some(c) = re.captures(&l) {
+                writeln!(
+                    &mut out,
+                    r"(MPS_KEY_{0}, $value:expr) => {{ unsafe {{
+                    let mut _arg: $crate::mps_arg_s = ::std::mem::zeroed();
+                    _arg.key = &$crate::_mps_key_{0};
+                    _arg.val.{1} = $value;
+                    _arg
+                }} }};",
+                    &c["name"],
+                    &c["field"]
+                )?;
+            }
         }
     }
 
@@ -64,12 +69,15 @@ fn main() {
         .include("mps-kit/code")
         .compile("libmps.a");
 
-    let mps_h = "mps-kit/code/mps.h";
-    let mps_arg_macro = generate_mps_args(mps_h).expect("failed to generate args macro");
+    let headers: Vec<String> = MPS_HEADERS
+        .iter()
+        .map(|h| format!("mps-kit/code/{}", h))
+        .collect();
+    let mps_arg_macro = generate_mps_args(&headers).expect("failed to generate args macro");
 
-    let bindings = bindgen::Builder::default()
-        .header("mps-kit/code/mps.h")
-        .header("mps-kit/code/mpsavm.h")
+    let bindings = headers
+        .iter()
+        .fold(bindgen::Builder::default(), |builder, h| builder.header(h.as_str()))
         .raw_line(mps_arg_macro)
         .clang_arg("-Imps-kit/code")
         .generate()
@@ -0,0 +1,249 @@
+//! Global allocator backed by an MPS arena
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::process;
+use std::ptr;
+use std::thread;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use ffi::{mps_addr_t, mps_alloc, mps_arena_class_vm, mps_arena_commit_limit_set, mps_arena_committed,
+          mps_arena_create_k, mps_arena_destroy, mps_arena_s, mps_arena_t, mps_class_mvff, mps_free,
+          mps_pool_create_k, mps_pool_s, mps_pool_t, mps_res_t, MPS_PF_ALIGN, MPS_RES_OK};
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const READY: usize = 2;
+const FAILED: usize = 3;
+
+/// A global allocator serving all allocations from an MVFF pool in its own
+/// virtual memory arena.
+///
+/// The arena and pool are created on the first allocation, which may well
+/// happen before `main`, and are never destroyed. Once the commit limit of
+/// the arena is reached, allocations fail. Alignments larger than the MPS
+/// platform alignment are supported by over-allocating.
+///
+/// This cannot use `VmArena` and `MvffPool`, as they allocate themselves.
+pub struct MpsGlobalAlloc {
+    capacity: usize,
+    commit_limit: Option<usize>,
+    state: AtomicUsize,
+    arena: AtomicPtr<mps_arena_s>,
+    pool: AtomicPtr<mps_pool_s>,
+}
+
+impl MpsGlobalAlloc {
+    /// Creates an allocator whose arena reserves `capacity` bytes of
+    /// address space
+    pub const fn new(capacity: usize) -> Self {
+        MpsGlobalAlloc {
+            capacity: capacity,
+            commit_limit: None,
+            state: AtomicUsize::new(UNINITIALIZED),
+            arena: AtomicPtr::new(ptr::null_mut()),
+            pool: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Creates an allocator whose arena reserves `capacity` bytes of
+    /// address space, and commits at most `commit_limit` bytes of memory
+    pub const fn with_commit_limit(capacity: usize, commit_limit: usize) -> Self {
+        MpsGlobalAlloc {
+            capacity: capacity,
+            commit_limit: Some(commit_limit),
+            state: AtomicUsize::new(UNINITIALIZED),
+            arena: AtomicPtr::new(ptr::null_mut()),
+            pool: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Return the total committed memory of the arena, zero if nothing has
+    /// been allocated yet.
+    pub fn committed(&self) -> usize {
+        match self.state.load(Ordering::Acquire) {
+            READY => unsafe { mps_arena_committed(self.arena.load(Ordering::Relaxed)) },
+            _ => 0,
+        }
+    }
+
+    /// Returns the pool, creating it if necessary
+    fn pool(&self) -> Option<mps_pool_t> {
+        loop {
+            match self.state.compare_exchange(UNINITIALIZED, INITIALIZING, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    let state = if unsafe { self.init() } { READY } else { FAILED };
+                    self.state.store(state, Ordering::Release);
+                }
+                Err(READY) => return Some(self.pool.load(Ordering::Relaxed)),
+                Err(FAILED) => return None,
+                // another thread is creating the pool, this must not allocate
+                Err(_) => thread::yield_now(),
+            }
+        }
+    }
+
+    unsafe fn init(&self) -> bool {
+        let mut arena: mps_arena_t = ptr::null_mut();
+        let args = mps_args! {
+            MPS_KEY_ARENA_SIZE: self.capacity,
+        };
+        if mps_arena_create_k(&mut arena, mps_arena_class_vm(), args) != MPS_RES_OK as mps_res_t {
+            return false;
+        }
+
+        let mut pool: mps_pool_t = ptr::null_mut();
+        let limit_ok = match self.commit_limit {
+            Some(limit) => mps_arena_commit_limit_set(arena, limit) == MPS_RES_OK as mps_res_t,
+            None => true,
+        };
+        if !limit_ok || mps_pool_create_k(&mut pool, arena, mps_class_mvff(), mps_args!{}) != MPS_RES_OK as mps_res_t {
+            mps_arena_destroy(arena);
+            return false;
+        }
+
+        self.arena.store(arena, Ordering::Relaxed);
+        self.pool.store(pool, Ordering::Relaxed);
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for MpsGlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pool = match self.pool() {
+            Some(pool) => pool,
+            None => return ptr::null_mut(),
+        };
+        let size = match block_size(layout.size(), layout.align()) {
+            Some(size) => size,
+            None => return ptr::null_mut(),
+        };
+
+        let mut addr: mps_addr_t = ptr::null_mut();
+        if mps_alloc(&mut addr, pool, size) != MPS_RES_OK as mps_res_t {
+            return ptr::null_mut();
+        }
+
+        if layout.align() <= MPS_PF_ALIGN as usize {
+            return addr as *mut u8;
+        }
+
+        // over-aligned blocks store their base in the word before them,
+        // there is always room as the base is aligned to a whole word
+        let base = addr as usize;
+        let aligned = (base + layout.align()) & !(layout.align() - 1);
+        *(aligned as *mut usize).offset(-1) = base;
+        aligned as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let base = if layout.align() <= MPS_PF_ALIGN as usize {
+            ptr as mps_addr_t
+        } else {
+            *(ptr as *mut usize).offset(-1) as mps_addr_t
+        };
+
+        // the layout has been checked by `alloc` already, a panic cannot
+        // unwind out of an allocator anyway
+        let size = block_size(layout.size(), layout.align()).unwrap_or_else(|| process::abort());
+        mps_free(self.pool.load(Ordering::Relaxed), base, size)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // blocks are rounded up, the new size might still fit
+        if block_size(new_size, layout.align()) == block_size(layout.size(), layout.align()) {
+            return ptr;
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Size of the pool block holding an allocation
+fn block_size(size: usize, align: usize) -> Option<usize> {
+    let pool_align = MPS_PF_ALIGN as usize;
+    let size = size.checked_add(pool_align - 1)? & !(pool_align - 1);
+    if align <= pool_align {
+        Some(size)
+    } else {
+        size.checked_add(align)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_alloc_over_aligned() {
+        static ALLOC: MpsGlobalAlloc = MpsGlobalAlloc::new(64 << 20);
+        for &align in &[1, 8, 64, 4096] {
+            unsafe {
+                let layout = Layout::from_size_align(100, align).unwrap();
+                let ptr = ALLOC.alloc(layout);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % align, 0);
+                ptr::write_bytes(ptr, 0xff, layout.size());
+                ALLOC.dealloc(ptr, layout);
+            }
+        }
+        assert!(ALLOC.committed() > 0);
+    }
+
+    #[test]
+    fn global_alloc_realloc() {
+        static ALLOC: MpsGlobalAlloc = MpsGlobalAlloc::new(64 << 20);
+        unsafe {
+            let layout = Layout::from_size_align(1, 1).unwrap();
+            let ptr = ALLOC.alloc(layout);
+            *ptr = 42;
+            assert_eq!(ALLOC.realloc(ptr, layout, 2), ptr);
+
+            let layout = Layout::from_size_align(2, 1).unwrap();
+            let moved = ALLOC.realloc(ptr, layout, 1 << 16);
+            assert_eq!(*moved, 42);
+            ALLOC.dealloc(moved, Layout::from_size_align(1 << 16, 1).unwrap());
+        }
+    }
+
+    #[test]
+    fn global_alloc_commit_limit() {
+        static ALLOC: MpsGlobalAlloc = MpsGlobalAlloc::with_commit_limit(64 << 20, 1 << 20);
+        unsafe {
+            let layout = Layout::from_size_align(4 << 20, 8).unwrap();
+            assert!(ALLOC.alloc(layout).is_null());
+
+            // the same allocation fits once the limit has been raised
+            let res = mps_arena_commit_limit_set(ALLOC.arena.load(Ordering::Relaxed), 32 << 20);
+            assert_eq!(res, MPS_RES_OK as mps_res_t);
+            let ptr = ALLOC.alloc(layout);
+            assert!(!ptr.is_null());
+            ALLOC.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn global_alloc_threads() {
+        static ALLOC: MpsGlobalAlloc = MpsGlobalAlloc::new(64 << 20);
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || unsafe {
+                    let layout = Layout::from_size_align(64 * (i + 1), 16).unwrap();
+                    for _ in 0..1000 {
+                        let ptr = ALLOC.alloc(layout);
+                        assert!(!ptr.is_null());
+                        ALLOC.dealloc(ptr, layout);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
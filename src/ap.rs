@@ -0,0 +1,476 @@
+//! Allocation points
+
+use std::mem;
+use std::process;
+use std::ptr;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{self, Ordering};
+
+use errors::{Error, Result};
+use ffi::{mps_addr_t, mps_alloc_pattern_ramp, mps_alloc_pattern_ramp_collect_all, mps_alloc_pattern_t,
+          mps_ap_alloc_pattern_begin, mps_ap_alloc_pattern_end, mps_ap_alloc_pattern_reset, mps_ap_create_k,
+          mps_ap_destroy, mps_ap_fill, mps_ap_frame_pop, mps_ap_frame_push, mps_ap_t, mps_ap_trip, mps_frame_t};
+use pool::{Pool, PoolRef};
+use root::Rank;
+
+/// An allocation point, used to allocate objects in automatically managed
+/// pools, and blocks in manual pools which do not support `mps_alloc`.
+///
+/// Allocation follows a two-phase protocol: a block is reserved, initialized
+/// as a valid object of the pool's format and then committed. Committing
+/// fails if the collector ran in between, in which case the object has to be
+/// reserved and initialized again.
+///
+/// Allocation points are created by the pools which support them and keep
+/// their pool alive. They must not be shared between threads.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/allocation.html#allocation-points)
+/// for details.
+pub struct AllocationPoint {
+    ap: mps_ap_t,
+    pool: PoolRef,
+    rank: Option<Rank>,
+    // number of resets, guards of patterns begun before one must not end
+    // the patterns begun after it
+    pattern_resets: usize,
+}
+
+impl AllocationPoint {
+    /// Creates a new allocation point on `pool`. The rank is only passed on
+    /// if set, pools are responsible for only requesting ranks they support.
+    pub(crate) fn new(pool: PoolRef, rank: Option<Rank>) -> Result<Self> {
+        let mut args = Vec::new();
+        if let Some(rank) = rank {
+            args.push(mps_arg_s!(MPS_KEY_RANK, rank.as_raw()));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let ap = unsafe {
+            let mut ap: mps_ap_t = ptr::null_mut();
+            let res = mps_ap_create_k(&mut ap, pool.as_raw(), args.as_mut_ptr());
+            Error::result(res).map(|_| ap)
+        }?;
+
+        Ok(AllocationPoint {
+            ap,
+            pool,
+            rank,
+            pattern_resets: 0,
+        })
+    }
+
+    /// Return the raw allocation point pointer
+    pub fn as_raw(&self) -> mps_ap_t {
+        self.ap
+    }
+
+    /// Access the pool this allocation point belongs to
+    pub fn pool(&self) -> &PoolRef {
+        &self.pool
+    }
+
+    /// Rank of the references in the allocated objects, `None` if the pool
+    /// chose its default rank
+    pub fn rank(&self) -> Option<Rank> {
+        self.rank
+    }
+
+    /// Allocates an object of `size` bytes, initialized by `init`.
+    ///
+    /// The block is reserved, handed to `init` and committed. If the commit
+    /// fails because the collector ran in between, a new block is reserved
+    /// and `init` is called again, so it must initialize the whole block on
+    /// every call. The address is only returned once the object has been
+    /// committed, the collector never sees a partially initialized object.
+    /// If `init` panics, the process is aborted: the reserved block can
+    /// neither be given back nor committed.
+    ///
+    /// # Safety
+    ///
+    /// `init` must initialize the block as a valid object of the pool's
+    /// format. The object is only kept alive by references to it, the
+    /// returned address must be stored in a scanned location before the
+    /// collector is allowed to run.
+    pub unsafe fn alloc<F>(&mut self, size: usize, mut init: F) -> Result<mps_addr_t>
+    where
+        F: FnMut(mps_addr_t),
+    {
+        self.check_size(size)?;
+
+        loop {
+            let addr = self.reserve(size)?;
+            let guard = AbortOnUnwind;
+            init(addr);
+            mem::forget(guard);
+            if self.commit(addr, size) {
+                return Ok(addr);
+            }
+        }
+    }
+
+    /// Allocates several objects at once, of the given `sizes`, initialized
+    /// by `init`.
+    ///
+    /// A single block of the combined size is reserved, and `init` is
+    /// handed the addresses of the objects in it. The objects are committed
+    /// together, so either all of them are allocated or none is. As with
+    /// `alloc`, `init` is called again on a fresh block if the commit fails,
+    /// and the process is aborted if it panics.
+    ///
+    /// # Safety
+    ///
+    /// `init` must initialize every object as a valid object of the pool's
+    /// format, so that the whole block can be walked object by object. See
+    /// `alloc` for the other requirements.
+    pub unsafe fn alloc_many<F>(&mut self, sizes: &[usize], mut init: F) -> Result<Vec<mps_addr_t>>
+    where
+        F: FnMut(&[mps_addr_t]),
+    {
+        let mut offsets = Vec::with_capacity(sizes.len());
+        let mut total: usize = 0;
+        for &size in sizes {
+            self.check_size(size)?;
+            offsets.push(total);
+            total = total
+                .checked_add(size)
+                .ok_or(Error::InvalidArgument("combined object size overflows"))?;
+        }
+        if total == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut objects = Vec::with_capacity(sizes.len());
+        loop {
+            let base = self.reserve(total)?;
+            objects.clear();
+            objects.extend(offsets.iter().map(|&offset| (base as *mut u8).offset(offset as isize) as mps_addr_t));
+            let guard = AbortOnUnwind;
+            init(&objects);
+            mem::forget(guard);
+            if self.commit(base, total) {
+                return Ok(objects);
+            }
+        }
+    }
+
+    /// Checks that an object of `size` bytes can be reserved, a nonzero
+    /// multiple of the pool's alignment
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size == 0 || size % self.pool.align() != 0 {
+            return Err(Error::InvalidArgument("object size must be a nonzero multiple of the alignment"));
+        }
+
+        Ok(())
+    }
+
+    /// Reserves a block of `size` bytes. Prefer `alloc`, which takes care
+    /// of the whole allocation protocol.
+    ///
+    /// See: [`mps_reserve`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/allocation.html#c.mps_reserve)
+    ///
+    /// # Safety
+    ///
+    /// `size` must be a nonzero multiple of the pool's alignment. The block
+    /// must be initialized as a valid object of the pool's format before it
+    /// is committed, and no other block may be reserved in the meantime.
+    #[inline(always)]
+    pub unsafe fn reserve(&mut self, size: usize) -> Result<mps_addr_t> {
+        // the inline part of the mps_reserve macro, the fields are volatile
+        // as the collector changes them when it runs
+        let ap = self.ap;
+        let alloc = ptr::read_volatile(&(*ap).alloc) as usize;
+        let next = alloc.wrapping_add(size);
+        if next > alloc && next <= ptr::read_volatile(&(*ap).limit) as usize {
+            ptr::write_volatile(&mut (*ap).alloc, next as mps_addr_t);
+            return Ok(ptr::read_volatile(&(*ap).init));
+        }
+
+        self.fill(size)
+    }
+
+    /// Reserves a block once the buffer of the allocation point is
+    /// exhausted
+    #[cold]
+    #[inline(never)]
+    unsafe fn fill(&mut self, size: usize) -> Result<mps_addr_t> {
+        let mut addr: mps_addr_t = ptr::null_mut();
+        Error::result(mps_ap_fill(&mut addr, self.ap, size))?;
+        Ok(addr)
+    }
+
+    /// Commits the most recently reserved block. Returns `false` if the
+    /// block has to be reserved and initialized again.
+    ///
+    /// See: [`mps_commit`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/allocation.html#c.mps_commit)
+    ///
+    /// # Safety
+    ///
+    /// `addr` and `size` must be those of the last call to `reserve`, and
+    /// the block must have been initialized.
+    #[inline(always)]
+    pub unsafe fn commit(&mut self, addr: mps_addr_t, size: usize) -> bool {
+        // the inline part of the mps_commit macro, the object must be
+        // initialized before it is exposed, and the limit must only be
+        // checked afterwards
+        let ap = self.ap;
+        atomic::compiler_fence(Ordering::SeqCst);
+        ptr::write_volatile(&mut (*ap).init, ptr::read_volatile(&(*ap).alloc));
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        !ptr::read_volatile(&(*ap).limit).is_null() || self.trip(addr, size)
+    }
+
+    /// Checks whether a commit succeeded after the collector ran
+    #[cold]
+    #[inline(never)]
+    unsafe fn trip(&mut self, addr: mps_addr_t, size: usize) -> bool {
+        mps_ap_trip(self.ap, addr, size) != 0
+    }
+
+    /// Pushes a new allocation frame.
+    ///
+    /// Allocation continues through the returned guard. All objects
+    /// allocated since the frame was pushed are declared dead once it is
+    /// popped, either by `FrameGuard::pop_frame` or on drop. Only SNC pools
+    /// reclaim them, other pools ignore frames.
+    ///
+    /// See: [`mps_ap_frame_push`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/frame.html#c.mps_ap_frame_push)
+    pub fn push_frame<'a>(&'a mut self) -> Result<FrameGuard<'a>> {
+        let frame = unsafe {
+            let mut frame: mps_frame_t = ptr::null_mut();
+            Error::result(mps_ap_frame_push(&mut frame, self.ap)).map(|_| frame)
+        }?;
+
+        Ok(FrameGuard {
+            ap: self,
+            frame: frame,
+            popped: false,
+        })
+    }
+
+    /// Begins an allocation pattern, a hint to the collector about the
+    /// objects allocated until it ends.
+    ///
+    /// Allocation continues through the returned guard. The pattern ends
+    /// on drop or by `PatternGuard::end`, patterns can be nested.
+    ///
+    /// See: [`mps_ap_alloc_pattern_begin`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/pattern.html#c.mps_ap_alloc_pattern_begin)
+    pub fn begin_pattern<'a>(&'a mut self, pattern: AllocPattern) -> Result<PatternGuard<'a>> {
+        let pattern = pattern.as_raw();
+        Error::result(unsafe { mps_ap_alloc_pattern_begin(self.ap, pattern) })?;
+
+        Ok(PatternGuard {
+            resets: self.pattern_resets,
+            ap: self,
+            pattern: pattern,
+            ended: false,
+        })
+    }
+
+    /// Ends all allocation patterns of the allocation point. Useful when
+    /// patterns have been left by an error. The guards of the reset
+    /// patterns do nothing once dropped, and fail to `end` them.
+    ///
+    /// See: [`mps_ap_alloc_pattern_reset`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/pattern.html#c.mps_ap_alloc_pattern_reset)
+    pub fn reset_patterns(&mut self) -> Result<()> {
+        Error::result(unsafe { mps_ap_alloc_pattern_reset(self.ap) })?;
+        self.pattern_resets += 1;
+        Ok(())
+    }
+}
+
+/// Aborts the process when dropped, which only happens if the initializer
+/// of a reserved block unwinds. The block cannot be given back to the
+/// allocation point, and committing it would expose garbage to the
+/// collector.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        process::abort()
+    }
+}
+
+/// Allocation patterns supported by the MPS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocPattern {
+    /// Most objects allocated during the pattern survive until it ends,
+    /// and then die. Collections are postponed until then.
+    Ramp,
+    /// Like `Ramp`, but a full collection is done once the pattern ends.
+    RampCollectAll,
+}
+
+impl AllocPattern {
+    fn as_raw(&self) -> mps_alloc_pattern_t {
+        unsafe {
+            match *self {
+                AllocPattern::Ramp => mps_alloc_pattern_ramp(),
+                AllocPattern::RampCollectAll => mps_alloc_pattern_ramp_collect_all(),
+            }
+        }
+    }
+}
+
+/// An allocation pattern begun on an allocation point.
+///
+/// The guard borrows the allocation point, so patterns end in the reverse
+/// order they were begun. The pattern ends on drop, ignoring any error,
+/// use `PatternGuard::end` to check for them.
+#[must_use]
+pub struct PatternGuard<'ap> {
+    ap: &'ap mut AllocationPoint,
+    pattern: mps_alloc_pattern_t,
+    resets: usize,
+    ended: bool,
+}
+
+impl<'ap> PatternGuard<'ap> {
+    /// Ends the pattern.
+    ///
+    /// See: [`mps_ap_alloc_pattern_end`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/pattern.html#c.mps_ap_alloc_pattern_end)
+    pub fn end(mut self) -> Result<()> {
+        self.ended = true;
+        if self.resets != self.ap.pattern_resets {
+            return Err(Error::InvalidArgument("allocation pattern has been reset"));
+        }
+        Error::result(unsafe { mps_ap_alloc_pattern_end(self.ap.ap, self.pattern) })
+    }
+}
+
+impl<'ap> Deref for PatternGuard<'ap> {
+    type Target = AllocationPoint;
+
+    fn deref(&self) -> &AllocationPoint {
+        self.ap
+    }
+}
+
+impl<'ap> DerefMut for PatternGuard<'ap> {
+    fn deref_mut(&mut self) -> &mut AllocationPoint {
+        self.ap
+    }
+}
+
+impl<'ap> Drop for PatternGuard<'ap> {
+    fn drop(&mut self) {
+        // the pattern is gone once reset, the allocation point might be in
+        // a newer one of the same kind
+        if !self.ended && self.resets == self.ap.pattern_resets {
+            unsafe { mps_ap_alloc_pattern_end(self.ap.ap, self.pattern) };
+        }
+    }
+}
+
+/// An allocation frame pushed on an allocation point.
+///
+/// The guard borrows the allocation point, so frames can only be popped in
+/// the reverse order they were pushed. The frame is popped on drop.
+#[must_use]
+pub struct FrameGuard<'ap> {
+    ap: &'ap mut AllocationPoint,
+    frame: mps_frame_t,
+    popped: bool,
+}
+
+impl<'ap> FrameGuard<'ap> {
+    /// Pops the frame, declaring all objects allocated since it was pushed
+    /// dead.
+    ///
+    /// See: [`mps_ap_frame_pop`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/frame.html#c.mps_ap_frame_pop)
+    pub fn pop_frame(mut self) -> Result<()> {
+        self.popped = true;
+        Error::result(unsafe { mps_ap_frame_pop(self.ap.ap, self.frame) })
+    }
+}
+
+impl<'ap> Deref for FrameGuard<'ap> {
+    type Target = AllocationPoint;
+
+    fn deref(&self) -> &AllocationPoint {
+        self.ap
+    }
+}
+
+impl<'ap> DerefMut for FrameGuard<'ap> {
+    fn deref_mut(&mut self) -> &mut AllocationPoint {
+        self.ap
+    }
+}
+
+impl<'ap> Drop for FrameGuard<'ap> {
+    fn drop(&mut self) {
+        if !self.popped {
+            unsafe { mps_ap_frame_pop(self.ap.ap, self.frame) };
+        }
+    }
+}
+
+impl Drop for AllocationPoint {
+    fn drop(&mut self) {
+        // the pool is only released after this
+        unsafe { mps_ap_destroy(self.ap) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use fmt::area::{alloc_leaf, alloc_leaves, AreaFormat};
+    use pool::snc::SncPool;
+    use test_util::Untagged;
+
+    #[test]
+    fn ap_alloc_invalid_size() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena).unwrap();
+        let pool = SncPool::new(fmt).unwrap();
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+
+        for &size in &[0, ap.pool().align() + 1] {
+            let res = unsafe { ap.alloc(size, |_| panic!("reserved an invalid size")) };
+            assert!(res.is_err());
+        }
+    }
+
+    #[test]
+    fn ap_fast_path_refills() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena).unwrap();
+        let pool = SncPool::new(fmt).unwrap();
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+
+        // enough objects to exhaust several buffers
+        let objects: Vec<_> = (0..10000u64)
+            .map(|i| unsafe { alloc_leaf(&mut ap, 0, &i.to_le_bytes()).unwrap() })
+            .collect();
+        for (i, &addr) in objects.iter().enumerate() {
+            let payload = unsafe { slice::from_raw_parts((addr as *const u64).offset(1) as *const u8, 8) };
+            assert_eq!(payload, &(i as u64).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn ap_alloc_many() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena).unwrap();
+        let pool = SncPool::new(fmt).unwrap();
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+
+        let payloads: &[&[u8]] = &[b"node", b"a longer tail array", b"x"];
+        let objects = unsafe { alloc_leaves(&mut ap, 0, payloads).unwrap() };
+        assert_eq!(objects.len(), 3);
+        for (&addr, payload) in objects.iter().zip(payloads) {
+            let data = unsafe { slice::from_raw_parts((addr as *const u64).offset(1) as *const u8, payload.len()) };
+            assert_eq!(&data, payload);
+        }
+        assert!(objects.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let res = unsafe { ap.alloc_many(&[16, 0], |_| panic!("reserved an invalid size")) };
+        assert!(res.is_err());
+        assert!(unsafe { ap.alloc_many(&[], |_| panic!("reserved an empty block")) }.unwrap().is_empty());
+    }
+}
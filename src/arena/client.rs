@@ -0,0 +1,167 @@
+//! Client memory arena
+
+use std::ptr;
+use std::slice;
+
+use errors::{Result, Error};
+use ffi::{mps_arena_class_cl, mps_arena_create_k, mps_arena_extend, mps_arena_t, mps_addr_t};
+use arena::{Arena, ArenaGuard, ArenaRef, RawArena};
+
+/// Grain size of client arenas, as they are created without
+/// `MPS_KEY_ARENA_GRAIN_SIZE` (`ARENA_CLIENT_GRAIN_SIZE`)
+const GRAIN_SIZE: usize = 8192;
+
+/// An MPS arena which manages memory provided by the client program.
+///
+/// The arena owns every block of memory handed to it and keeps them alive
+/// until the arena itself has been destroyed. Any type which can be viewed
+/// as a mutable byte slice, such as a `Vec<u8>` or a memory mapped region,
+/// can be used as a block.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#client-arenas)
+/// for details.
+pub struct ClientArena {
+    arena: RawArena,
+}
+
+impl ClientArena {
+    /// Creates a new client arena managing the given block of memory.
+    ///
+    /// Returns `Error::InsufficientMemory` if the block is too small to hold
+    /// the internal arena structures.
+    pub fn new<B: AsMut<[u8]> + 'static>(block: B) -> Result<Self> {
+        let mut block: Box<AsMut<[u8]>> = Box::new(block);
+        let (base, size) = {
+            let mem = (*block).as_mut();
+            (mem.as_mut_ptr() as mps_addr_t, mem.len())
+        };
+
+        let args = mps_args! {
+            MPS_KEY_ARENA_CL_BASE: base,
+            MPS_KEY_ARENA_SIZE: size,
+        };
+
+        let mut arena = unsafe {
+            let mut arena: mps_arena_t = ptr::null_mut();
+            let res = mps_arena_create_k(&mut arena, mps_arena_class_cl(), args);
+
+            Error::result(res).map(|_| RawArena::new(arena, GRAIN_SIZE))
+        }?;
+        arena.blocks.push(block);
+
+        Ok(ClientArena { arena: arena })
+    }
+
+    /// Creates a new client arena managing a fixed address range.
+    ///
+    /// The memory is not owned by the arena and will not be released when
+    /// the arena is dropped.
+    ///
+    /// # Safety
+    ///
+    /// The range `base..base + size` must be valid for reads and writes,
+    /// and must not be accessed by anything but the arena for as long as
+    /// the arena lives.
+    pub unsafe fn from_raw_parts(base: *mut u8, size: usize) -> Result<Self> {
+        Self::new(RawBlock { base, size })
+    }
+
+    /// Extends the arena with another block of memory.
+    ///
+    /// Returns `Error::InvalidArgument` if the block does not contain at
+    /// least one whole arena grain of 8 KiB once its base is aligned.
+    ///
+    /// See: [`mps_arena_extend`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_extend)
+    pub fn extend<B: AsMut<[u8]> + 'static>(&mut self, block: B) -> Result<()> {
+        let mut block: Box<AsMut<[u8]>> = Box::new(block);
+        let res = unsafe {
+            let mem = (*block).as_mut();
+            let base = mem.as_mut_ptr() as usize;
+            // the MPS asserts that an aligned grain fits
+            let offset = base.wrapping_neg() % GRAIN_SIZE;
+            if mem.len() < offset + GRAIN_SIZE {
+                return Err(Error::InvalidArgument("arena block is too small"));
+            }
+
+            mps_arena_extend(self.as_raw(), mem.as_mut_ptr() as mps_addr_t, mem.len())
+        };
+
+        Error::result(res).map(|_| self.arena.blocks.push(block))
+    }
+
+    /// Extends the arena with a fixed address range.
+    ///
+    /// # Safety
+    ///
+    /// See `ClientArena::from_raw_parts`.
+    pub unsafe fn extend_raw(&mut self, base: *mut u8, size: usize) -> Result<()> {
+        self.extend(RawBlock { base, size })
+    }
+
+    /// Parks the arena, see `ArenaRef::park`
+    pub fn park<'a>(&'a self) -> ArenaGuard<'a> {
+        self.arena.park()
+    }
+
+    /// Clamps the arena, see `ArenaRef::clamp`
+    pub fn clamp<'a>(&'a self) -> ArenaGuard<'a> {
+        self.arena.clamp()
+    }
+}
+
+impl Arena for ClientArena {
+    fn as_raw(&self) -> mps_arena_t {
+        self.arena.as_raw()
+    }
+}
+
+impl Into<ArenaRef> for ClientArena {
+    fn into(self) -> ArenaRef {
+        ArenaRef::new(self.arena)
+    }
+}
+
+/// Address range which is not owned by the arena.
+struct RawBlock {
+    base: *mut u8,
+    size: usize,
+}
+
+impl AsMut<[u8]> for RawBlock {
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.base, self.size) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_TEST_SIZE: usize = 16 << 20;
+
+    #[test]
+    fn arena_create_and_drop() {
+        let _ = ClientArena::new(vec![0u8; BLOCK_TEST_SIZE]).unwrap();
+    }
+
+    #[test]
+    fn arena_too_small() {
+        let res = ClientArena::new(vec![0u8; 64]);
+        assert_eq!(res.err(), Some(Error::InsufficientMemory));
+    }
+
+    #[test]
+    fn arena_extend() {
+        let mut arena = ClientArena::new(vec![0u8; BLOCK_TEST_SIZE]).unwrap();
+        let reserved = arena.reserved();
+        arena.extend(vec![0u8; BLOCK_TEST_SIZE]).unwrap();
+        assert!(arena.reserved() > reserved);
+    }
+
+    #[test]
+    fn arena_extend_too_small() {
+        let mut arena = ClientArena::new(vec![0u8; BLOCK_TEST_SIZE]).unwrap();
+        assert!(arena.extend(Vec::new()).is_err());
+        assert!(arena.extend(vec![0u8; 100]).is_err());
+    }
+}
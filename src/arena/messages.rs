@@ -0,0 +1,259 @@
+//! Message queue
+
+use std::ffi::CStr;
+use std::ptr;
+
+use ffi::{mps_addr_t, mps_arena_t, mps_clock_t, mps_message_clock, mps_message_discard,
+          mps_message_finalization_ref, mps_message_gc_condemned_size, mps_message_gc_live_size,
+          mps_message_gc_not_condemned_size, mps_message_gc_start_why, mps_message_get, mps_message_poll,
+          mps_message_queue_type, mps_message_t, mps_message_type, mps_message_type_disable, mps_message_type_enable,
+          mps_message_type_t, _mps_MESSAGE_TYPE_FINALIZATION, _mps_MESSAGE_TYPE_GC, _mps_MESSAGE_TYPE_GC_START};
+use arena::{Arena, ArenaRef};
+
+/// Types of messages posted by the MPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    /// A registered object has become finalizable.
+    Finalization,
+    /// A garbage collection has completed.
+    Gc,
+    /// A garbage collection has started.
+    GcStart,
+}
+
+impl MessageType {
+    /// Returns the raw message type value.
+    pub fn as_raw(&self) -> mps_message_type_t {
+        let ty = match *self {
+            MessageType::Finalization => _mps_MESSAGE_TYPE_FINALIZATION,
+            MessageType::Gc => _mps_MESSAGE_TYPE_GC,
+            MessageType::GcStart => _mps_MESSAGE_TYPE_GC_START,
+        };
+
+        ty as mps_message_type_t
+    }
+}
+
+/// A message retrieved from the message queue of an arena.
+///
+/// Please refer to the [messages](https://www.ravenbrook.com/project/mps/master/manual/html/topic/message.html)
+/// chapter in the Memory Pool System reference for more details.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// A garbage collection has completed. Sizes are in bytes.
+    Gc {
+        live: usize,
+        condemned: usize,
+        not_condemned: usize,
+        clock: mps_clock_t,
+    },
+    /// A garbage collection has started for the given reason.
+    GcStart { why: String, clock: mps_clock_t },
+    /// The object at this address is finalizable.
+    ///
+    /// The object is only kept alive by the message itself, which has already
+    /// been discarded. It must be made reachable again before the next
+    /// collection if it is still needed.
+    Finalization(mps_addr_t),
+}
+
+impl Message {
+    /// Converts a raw message which has not been discarded yet.
+    unsafe fn from_raw(arena: mps_arena_t, message: mps_message_t) -> Option<Self> {
+        let ty = mps_message_type(arena, message);
+        let clock = mps_message_clock(arena, message);
+
+        if ty == MessageType::Gc.as_raw() {
+            Some(Message::Gc {
+                live: mps_message_gc_live_size(arena, message),
+                condemned: mps_message_gc_condemned_size(arena, message),
+                not_condemned: mps_message_gc_not_condemned_size(arena, message),
+                clock: clock,
+            })
+        } else if ty == MessageType::GcStart.as_raw() {
+            let why = CStr::from_ptr(mps_message_gc_start_why(arena, message));
+            Some(Message::GcStart {
+                why: why.to_string_lossy().into_owned(),
+                clock: clock,
+            })
+        } else if ty == MessageType::Finalization.as_raw() {
+            let mut addr: mps_addr_t = ptr::null_mut();
+            mps_message_finalization_ref(&mut addr, arena, message);
+            Some(Message::Finalization(addr))
+        } else {
+            None
+        }
+    }
+}
+
+/// Iterator over the message queue of an arena.
+///
+/// Only messages of the enabled types are retrieved. Messages are discarded
+/// as soon as they have been retrieved. Dropping the queue discards every
+/// message which is still pending and disables all message types it has
+/// enabled, so that no message is leaked.
+///
+/// The MPS has only one message queue per arena, a message type should only
+/// be enabled by one `MessageQueue` of an arena at a time.
+pub struct MessageQueue {
+    arena: ArenaRef,
+    enabled: Vec<MessageType>,
+}
+
+impl MessageQueue {
+    /// Creates a new queue and enables the given message types.
+    pub fn new<A: Into<ArenaRef>>(arena: A, types: &[MessageType]) -> Self {
+        let mut queue = MessageQueue {
+            arena: arena.into(),
+            enabled: Vec::new(),
+        };
+
+        for &ty in types {
+            queue.enable(ty);
+        }
+
+        queue
+    }
+
+    /// Enables the posting of messages of the given type.
+    ///
+    /// See: [`mps_message_type_enable`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/message.html#c.mps_message_type_enable)
+    pub fn enable(&mut self, ty: MessageType) {
+        if !self.enabled.contains(&ty) {
+            unsafe { mps_message_type_enable(self.arena.as_raw(), ty.as_raw()) }
+            self.enabled.push(ty);
+        }
+    }
+
+    /// Disables the posting of messages of the given type. Pending messages
+    /// of this type are discarded.
+    ///
+    /// See: [`mps_message_type_disable`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/message.html#c.mps_message_type_disable)
+    pub fn disable(&mut self, ty: MessageType) {
+        if let Some(i) = self.enabled.iter().position(|&t| t == ty) {
+            unsafe { mps_message_type_disable(self.arena.as_raw(), ty.as_raw()) }
+            self.enabled.swap_remove(i);
+        }
+    }
+
+    /// Returns true if there are messages pending.
+    ///
+    /// See: [`mps_message_poll`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/message.html#c.mps_message_poll)
+    pub fn poll(&self) -> bool {
+        unsafe { mps_message_poll(self.arena.as_raw()) != 0 }
+    }
+
+    /// Access the arena this queue belongs to
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    /// Retrieves the oldest pending message of an enabled type, in the
+    /// order the messages were posted
+    fn get(&self) -> Option<mps_message_t> {
+        let arena = self.arena.as_raw();
+        let mut message: mps_message_t = ptr::null_mut();
+        unsafe {
+            let mut oldest: mps_message_type_t = 0;
+            if mps_message_queue_type(&mut oldest, arena) == 0 {
+                return None;
+            }
+
+            // the oldest message might be of a type enabled elsewhere, e.g.
+            // by a finalization registry, it is left for its owner then
+            let enabled = self.enabled.iter().map(MessageType::as_raw);
+            let oldest = Some(oldest).filter(|&ty| self.enabled.iter().any(|t| t.as_raw() == ty));
+            if oldest.into_iter().chain(enabled).any(|ty| mps_message_get(&mut message, arena, ty) != 0) {
+                Some(message)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl Iterator for MessageQueue {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        let arena = self.arena.as_raw();
+        loop {
+            let message = self.get()?;
+            unsafe {
+                let msg = Message::from_raw(arena, message);
+                mps_message_discard(arena, message);
+                // messages of unknown types are skipped
+                if msg.is_some() {
+                    return msg;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        for _ in &mut *self {}
+
+        for ty in self.enabled.clone() {
+            self.disable(ty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::vm::VmArena;
+    use chain::{Chain, GenParams};
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use pool::amc::AmcPool;
+    use root::Rank;
+    use test_util::Untagged;
+
+    #[test]
+    fn queue_enable_and_drop() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let mut queue = MessageQueue::new(arena.clone(), &[MessageType::Gc, MessageType::GcStart]);
+        queue.enable(MessageType::Gc);
+        assert_eq!(queue.enabled.len(), 2);
+        queue.disable(MessageType::GcStart);
+        assert_eq!(queue.enabled, vec![MessageType::Gc]);
+
+        arena.collect().unwrap();
+        for msg in &mut queue {
+            match msg {
+                Message::Gc { .. } => (),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+        assert!(!queue.poll());
+    }
+
+    #[test]
+    fn queue_posting_order() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }]).unwrap();
+        let pool = AmcPool::new(fmt, chain).unwrap();
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+        let mut queue = MessageQueue::new(arena.clone(), &[MessageType::Gc, MessageType::GcStart]);
+
+        // every collection posts its start before its end
+        for _ in 0..2 {
+            for _ in 0..100 {
+                unsafe { alloc_leaf(&mut ap, 0, b"garbage").unwrap() };
+            }
+            arena.collect().unwrap();
+        }
+        let started: Vec<_> = queue
+            .by_ref()
+            .map(|msg| match msg {
+                Message::GcStart { .. } => true,
+                _ => false,
+            })
+            .collect();
+        assert!(!started.is_empty());
+        assert!(started.chunks(2).all(|pair| pair == &[true, false]));
+    }
+}
@@ -1,10 +1,17 @@
 //! Arena implementations
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use ffi::{mps_arena_committed, mps_arena_destroy, mps_arena_reserved, mps_arena_t};
+use errors::{Error, Result};
+use ffi::{mps_arena_clamp, mps_arena_collect, mps_arena_commit_limit, mps_arena_commit_limit_set,
+          mps_arena_committed, mps_arena_destroy, mps_arena_park, mps_arena_release, mps_arena_reserved,
+          mps_arena_spare_commit_limit, mps_arena_spare_commit_limit_set, mps_arena_spare_committed,
+          mps_arena_start_collect, mps_arena_step, mps_arena_t, MPS_RES_FAIL};
 
 pub mod vm;
+pub mod client;
+pub mod messages;
 
 /// Generic MPS arena interface
 pub trait Arena {
@@ -26,6 +33,124 @@ pub trait Arena {
     fn reserved(&self) -> usize {
         unsafe { mps_arena_reserved(self.as_raw()) }
     }
+
+    /// Return the current commit limit of the arena, in bytes.
+    ///
+    /// See: [`mps_arena_commit_limit`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_commit_limit)
+    fn commit_limit(&self) -> usize {
+        unsafe { mps_arena_commit_limit(self.as_raw()) }
+    }
+
+    /// Change the commit limit of the arena.
+    ///
+    /// Spare committed memory is released if necessary. Fails with
+    /// `Error::CommitLimit` if the arena already uses more memory than the
+    /// new limit allows.
+    ///
+    /// See: [`mps_arena_commit_limit_set`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_commit_limit_set)
+    fn set_commit_limit(&self, limit: usize) -> Result<()> {
+        let res = unsafe { mps_arena_commit_limit_set(self.as_raw(), limit) };
+        if res as u32 == MPS_RES_FAIL {
+            return Err(Error::CommitLimit);
+        }
+
+        Error::result(res)
+    }
+
+    /// Return the total amount of spare committed memory, in bytes.
+    ///
+    /// See: [`mps_arena_spare_committed`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_spare_committed)
+    fn spare_committed(&self) -> usize {
+        unsafe { mps_arena_spare_committed(self.as_raw()) }
+    }
+
+    /// Return the spare commit limit of the arena, in bytes.
+    ///
+    /// See: [`mps_arena_spare_commit_limit`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_spare_commit_limit)
+    fn spare_commit_limit(&self) -> usize {
+        unsafe { mps_arena_spare_commit_limit(self.as_raw()) }
+    }
+
+    /// Change the spare commit limit of the arena.
+    ///
+    /// See: [`mps_arena_spare_commit_limit_set`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_spare_commit_limit_set)
+    fn set_spare_commit_limit(&self, limit: usize) {
+        unsafe { mps_arena_spare_commit_limit_set(self.as_raw(), limit) }
+    }
+
+    /// Return a snapshot of the memory usage and limits of the arena.
+    fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget {
+            reserved: self.reserved(),
+            committed: self.commited(),
+            commit_limit: self.commit_limit(),
+            spare_committed: self.spare_committed(),
+            spare_commit_limit: self.spare_commit_limit(),
+        }
+    }
+
+    /// Puts the arena into the unclamped state. Prefer the guards returned
+    /// by `ArenaRef::park` and `ArenaRef::clamp`, this ignores them.
+    ///
+    /// See: [`mps_arena_release`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_release)
+    fn release(&self) {
+        unsafe { mps_arena_release(self.as_raw()) }
+    }
+
+    /// Collects the arena and leaves it in the parked state.
+    ///
+    /// See: [`mps_arena_collect`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_collect)
+    fn collect(&self) -> Result<()> {
+        Error::result(unsafe { mps_arena_collect(self.as_raw()) })
+    }
+
+    /// Requests a full collection of the arena and returns immediately.
+    ///
+    /// See: [`mps_arena_start_collect`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_start_collect)
+    fn start_collect(&self) -> Result<()> {
+        Error::result(unsafe { mps_arena_start_collect(self.as_raw()) })
+    }
+
+    /// Performs collection work for at most `interval` seconds. Returns
+    /// `true` if there was any work to do.
+    ///
+    /// See: [`mps_arena_step`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_step)
+    fn step(&self, interval: f64, multiplier: f64) -> bool {
+        unsafe { mps_arena_step(self.as_raw(), interval, multiplier) != 0 }
+    }
+}
+
+/// Memory usage and limits of an arena, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub reserved: usize,
+    pub committed: usize,
+    pub commit_limit: usize,
+    pub spare_committed: usize,
+    pub spare_commit_limit: usize,
+}
+
+impl MemoryBudget {
+    /// Amount of memory which can still be committed before the commit
+    /// limit is reached.
+    pub fn available(&self) -> usize {
+        self.commit_limit.saturating_sub(self.committed)
+    }
+}
+
+/// RAII-guard for a parked or clamped arena. Releases the arena on drop,
+/// unless other guards are still alive.
+#[must_use]
+pub struct ArenaGuard<'a> {
+    arena: &'a RawArena,
+}
+
+impl<'a> Drop for ArenaGuard<'a> {
+    fn drop(&mut self) {
+        if self.arena.guard_depth.fetch_sub(1, Ordering::AcqRel) == 1 {
+            unsafe { mps_arena_release(self.arena.arena) }
+        }
+    }
 }
 
 /// Clone-able handle to a type-erased arena.
@@ -35,16 +160,40 @@ pub trait Arena {
 /// or other resources which must not outlive the arena.
 #[derive(Clone)]
 pub struct ArenaRef {
-    arena: Arc<Arena>,
+    arena: Arc<RawArena>,
 }
 
 impl ArenaRef {
     /// Construct a initial reference for the given arena.
-    fn new<A: Arena + 'static>(arena: A) -> Self {
+    fn new(arena: RawArena) -> Self {
         ArenaRef {
             arena: Arc::new(arena),
         }
     }
+
+    /// Puts the arena into the parked state, finishing any ongoing
+    /// collection. Guards nest, the arena is only released once the last
+    /// guard is dropped, parked arenas stay parked when clamped.
+    ///
+    /// See: [`mps_arena_park`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_park)
+    pub fn park<'a>(&'a self) -> ArenaGuard<'a> {
+        self.arena.park()
+    }
+
+    /// Puts the arena into the clamped state, no object motion will occur
+    /// until the last guard is dropped. Clamping a parked arena leaves it
+    /// parked.
+    ///
+    /// See: [`mps_arena_clamp`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_clamp)
+    pub fn clamp<'a>(&'a self) -> ArenaGuard<'a> {
+        self.arena.clamp()
+    }
+
+    /// Lower bound of the grain size of the arena, the granularity with
+    /// which it hands out memory to pools
+    pub(crate) fn grain_size(&self) -> usize {
+        self.arena.grain_size
+    }
 }
 
 impl Arena for ArenaRef {
@@ -53,11 +202,39 @@ impl Arena for ArenaRef {
     }
 }
 
-
-
-/// RAII-handle for a raw arena pointer. Destroys the underlying arena on drop.
+/// RAII-handle for a raw arena pointer. Destroys the underlying arena on
+/// drop, before releasing the client memory it manages.
 struct RawArena {
     arena: mps_arena_t,
+    // number of live guards returned by `park` and `clamp`
+    guard_depth: AtomicUsize,
+    grain_size: usize,
+    blocks: Vec<Box<AsMut<[u8]>>>,
+}
+
+impl RawArena {
+    fn new(arena: mps_arena_t, grain_size: usize) -> Self {
+        RawArena {
+            arena: arena,
+            guard_depth: AtomicUsize::new(0),
+            grain_size: grain_size,
+            blocks: Vec::new(),
+        }
+    }
+
+    fn park<'a>(&'a self) -> ArenaGuard<'a> {
+        self.guard_depth.fetch_add(1, Ordering::AcqRel);
+        unsafe { mps_arena_park(self.arena) }
+        ArenaGuard { arena: self }
+    }
+
+    fn clamp<'a>(&'a self) -> ArenaGuard<'a> {
+        // a parked arena is clamped already, clamping would restart it
+        if self.guard_depth.fetch_add(1, Ordering::AcqRel) == 0 {
+            unsafe { mps_arena_clamp(self.arena) }
+        }
+        ArenaGuard { arena: self }
+    }
 }
 
 /// This type impelents the `Arena` such that it can be wrapped inside of
@@ -70,6 +247,7 @@ impl Arena for RawArena {
 
 impl Drop for RawArena {
     fn drop(&mut self) {
+        // the blocks are only dropped afterwards
         unsafe { mps_arena_destroy(self.arena) }
     }
 }
\ No newline at end of file
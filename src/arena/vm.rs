@@ -3,8 +3,12 @@
 use std::ptr;
 
 use errors::{Result, Error};
-use ffi::{mps_arena_class_vm, mps_arena_create_k, mps_arena_t};
-use arena::{Arena, ArenaRef, RawArena};
+use ffi::{mps_arena_class_vm, mps_arena_create_k, mps_arena_t, mps_bool_t};
+use arena::{Arena, ArenaGuard, ArenaRef, RawArena};
+
+/// Smallest page size of the platforms supported by the MPS, the grain size
+/// of virtual memory arenas is rounded up to the page size
+const MIN_PAGE_SIZE: usize = 4096;
 
 /// An MPS arena backed by virtual memory.
 ///
@@ -17,21 +21,146 @@ pub struct VmArena {
 impl VmArena {
     /// Creates a new virtual memory arena with the specified initial size
     pub fn with_capacity(capacity: usize) -> Result<Self> {
-        let args = mps_args! {
-             MPS_KEY_ARENA_SIZE: capacity,
-        };
+        VmArenaBuilder::new().size(capacity).build()
+    }
+
+    /// Returns a builder to configure all arena creation parameters
+    pub fn builder() -> VmArenaBuilder {
+        VmArenaBuilder::new()
+    }
+
+    /// Parks the arena, see `ArenaRef::park`
+    pub fn park<'a>(&'a self) -> ArenaGuard<'a> {
+        self.inner.park()
+    }
+
+    /// Clamps the arena, see `ArenaRef::clamp`
+    pub fn clamp<'a>(&'a self) -> ArenaGuard<'a> {
+        self.inner.clamp()
+    }
+}
+
+/// Builder for virtual memory arenas.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly. Refer to the documentation of
+/// [`mps_arena_class_vm`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/arena.html#c.mps_arena_class_vm)
+/// for the meaning of the individual keyword arguments.
+#[derive(Debug, Clone, Default)]
+pub struct VmArenaBuilder {
+    size: Option<usize>,
+    grain_size: Option<usize>,
+    zoned: Option<bool>,
+    commit_limit: Option<usize>,
+    spare_commit_limit: Option<usize>,
+    pause_time: Option<f64>,
+}
+
+impl VmArenaBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Initial amount of virtual address space to reserve, in bytes
+    /// (`MPS_KEY_ARENA_SIZE`).
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Granularity with which the arena manages memory, must be a power
+    /// of two (`MPS_KEY_ARENA_GRAIN_SIZE`).
+    pub fn grain_size(mut self, grain_size: usize) -> Self {
+        self.grain_size = Some(grain_size);
+        self
+    }
+
+    /// Whether the arena places objects into address zones
+    /// (`MPS_KEY_ARENA_ZONED`).
+    pub fn zoned(mut self, zoned: bool) -> Self {
+        self.zoned = Some(zoned);
+        self
+    }
+
+    /// Maximum amount of memory the arena will commit, in bytes
+    /// (`MPS_KEY_COMMIT_LIMIT`).
+    pub fn commit_limit(mut self, limit: usize) -> Self {
+        self.commit_limit = Some(limit);
+        self
+    }
+
+    /// Maximum amount of spare committed memory, in bytes
+    /// (`MPS_KEY_SPARE_COMMIT_LIMIT`).
+    pub fn spare_commit_limit(mut self, limit: usize) -> Self {
+        self.spare_commit_limit = Some(limit);
+        self
+    }
+
+    /// Maximum time, in seconds, operations within the arena may pause the
+    /// client program (`MPS_KEY_PAUSE_TIME`).
+    pub fn pause_time(mut self, seconds: f64) -> Self {
+        self.pause_time = Some(seconds);
+        self
+    }
 
+    /// Validates the parameters and creates the arena
+    pub fn build(&self) -> Result<VmArena> {
+        self.validate()?;
+
+        let mut args = Vec::new();
+        if let Some(size) = self.size {
+            args.push(mps_arg_s!(MPS_KEY_ARENA_SIZE, size));
+        }
+        if let Some(grain_size) = self.grain_size {
+            args.push(mps_arg_s!(MPS_KEY_ARENA_GRAIN_SIZE, grain_size));
+        }
+        if let Some(zoned) = self.zoned {
+            args.push(mps_arg_s!(MPS_KEY_ARENA_ZONED, zoned as mps_bool_t));
+        }
+        if let Some(limit) = self.commit_limit {
+            args.push(mps_arg_s!(MPS_KEY_COMMIT_LIMIT, limit));
+        }
+        if let Some(limit) = self.spare_commit_limit {
+            args.push(mps_arg_s!(MPS_KEY_SPARE_COMMIT_LIMIT, limit));
+        }
+        if let Some(seconds) = self.pause_time {
+            args.push(mps_arg_s!(MPS_KEY_PAUSE_TIME, seconds));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let grain_size = self.grain_size.unwrap_or(0).max(MIN_PAGE_SIZE);
         let arena = unsafe {
             let mut arena: mps_arena_t = ptr::null_mut();
-            let res = mps_arena_create_k(&mut arena, mps_arena_class_vm(), args);
+            let res = mps_arena_create_k(&mut arena, mps_arena_class_vm(), args.as_mut_ptr());
 
-            Error::result(res).map(|_| RawArena { arena })
+            Error::result(res).map(|_| RawArena::new(arena, grain_size))
         }?;
 
         Ok(VmArena {
             inner: ArenaRef::new(arena),
         })
     }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(grain_size) = self.grain_size {
+            if !grain_size.is_power_of_two() {
+                return Err(Error::InvalidArgument("arena grain size must be a power of two"));
+            }
+        }
+        if let Some(size) = self.size {
+            if size == 0 {
+                return Err(Error::InvalidArgument("arena size must be nonzero"));
+            }
+        }
+        if let Some(seconds) = self.pause_time {
+            if !(seconds >= 0.0 && seconds.is_finite()) {
+                return Err(Error::InvalidArgument("arena pause time must be a non-negative number of seconds"));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Arena for VmArena {
@@ -62,4 +191,70 @@ mod tests {
         let arena = VmArena::with_capacity(ARENA_TEST_SIZE).unwrap();
         assert!(arena.reserved() > arena.commited());
     }
+
+    #[test]
+    fn arena_collect() {
+        let arena = VmArena::with_capacity(ARENA_TEST_SIZE).unwrap();
+        arena.collect().unwrap();
+        arena.release();
+        arena.start_collect().unwrap();
+        while arena.step(0.1, 0.0) {}
+    }
+
+    #[test]
+    fn arena_park_guard() {
+        use std::sync::atomic::Ordering;
+
+        let arena = VmArena::with_capacity(ARENA_TEST_SIZE).unwrap();
+        {
+            let _parked = arena.park();
+            {
+                let _clamped = arena.clamp();
+                let _inner = arena.park();
+                assert_eq!(arena.inner.arena.guard_depth.load(Ordering::Relaxed), 3);
+            }
+            // still parked by the outer guard
+            assert_eq!(arena.inner.arena.guard_depth.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(arena.inner.arena.guard_depth.load(Ordering::Relaxed), 0);
+        arena.collect().unwrap();
+    }
+
+    #[test]
+    fn arena_commit_limit() {
+        let arena = VmArena::with_capacity(ARENA_TEST_SIZE).unwrap();
+        let budget = arena.memory_budget();
+        assert_eq!(budget.committed, arena.commited());
+
+        arena.set_commit_limit(budget.committed + (1 << 20)).unwrap();
+        assert_eq!(arena.commit_limit(), budget.committed + (1 << 20));
+        assert_eq!(arena.set_commit_limit(0), Err(Error::CommitLimit));
+
+        arena.set_spare_commit_limit(0);
+        assert_eq!(arena.spare_commit_limit(), 0);
+        assert_eq!(arena.spare_committed(), 0);
+    }
+
+    #[test]
+    fn arena_builder() {
+        let arena = VmArena::builder()
+            .size(ARENA_TEST_SIZE)
+            .grain_size(1 << 16)
+            .zoned(false)
+            .commit_limit(ARENA_TEST_SIZE)
+            .spare_commit_limit(0)
+            .pause_time(0.01)
+            .build()
+            .unwrap();
+        assert!(arena.reserved() >= ARENA_TEST_SIZE);
+    }
+
+    #[test]
+    fn arena_builder_grain_size() {
+        let res = VmArena::builder().grain_size(3 << 12).build();
+        match res {
+            Err(Error::InvalidArgument(_)) => (),
+            _ => panic!("grain size was not rejected"),
+        }
+    }
 }
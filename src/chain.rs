@@ -0,0 +1,140 @@
+//! Generation chains
+
+use std::ptr;
+use std::sync::Arc;
+
+use ffi::{mps_chain_create, mps_chain_destroy, mps_chain_t, mps_gen_param_s};
+use arena::{Arena, ArenaRef};
+use errors::{Error, Result};
+
+/// Parameters of a single generation in a chain.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/collection.html#generation-chains)
+/// for details.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenParams {
+    /// Capacity of the generation, in kilobytes
+    pub capacity_kb: usize,
+    /// Predicted proportion of objects dying in a collection, between 0 and 1
+    pub mortality: f64,
+}
+
+/// A generation chain, needed to create automatically managed pools.
+pub struct Chain {
+    chain: ChainRef,
+}
+
+impl Chain {
+    /// Creates a new chain with the given generations, youngest first.
+    pub fn new<A: Into<ArenaRef>>(arena: A, gens: &[GenParams]) -> Result<Self> {
+        if gens.is_empty() {
+            return Err(Error::InvalidArgument("a chain needs at least one generation"));
+        }
+
+        let mut params = Vec::with_capacity(gens.len());
+        for gen in gens {
+            if gen.capacity_kb == 0 {
+                return Err(Error::InvalidArgument("generation capacity must be nonzero"));
+            }
+            if !(gen.mortality >= 0.0 && gen.mortality <= 1.0) {
+                return Err(Error::InvalidArgument("generation mortality must be between 0 and 1"));
+            }
+
+            params.push(mps_gen_param_s {
+                mps_capacity: gen.capacity_kb,
+                mps_mortality: gen.mortality,
+            });
+        }
+
+        let arena = arena.into();
+        let chain = unsafe {
+            let mut chain: mps_chain_t = ptr::null_mut();
+            let res = mps_chain_create(&mut chain, arena.as_raw(), params.len(), params.as_mut_ptr());
+            Error::result(res).map(|_| RawChain { chain })
+        }?;
+
+        Ok(Chain {
+            chain: ChainRef {
+                chain: Arc::new(chain),
+                gens: gens.len(),
+                arena: arena,
+            },
+        })
+    }
+
+    /// Return the raw chain pointer
+    pub fn as_raw(&self) -> mps_chain_t {
+        self.chain.as_raw()
+    }
+}
+
+impl Into<ChainRef> for Chain {
+    fn into(self) -> ChainRef {
+        self.chain
+    }
+}
+
+/// Clone-able handle to a generation chain.
+///
+/// By holding on to this handle, the chain and arena it belongs to will be
+/// kept alive. Pools using a chain hold on to it, so the chain is only
+/// destroyed after all of its pools.
+#[derive(Clone)]
+pub struct ChainRef {
+    // fields are dropped in order, the chain must go before its arena
+    chain: Arc<RawChain>,
+    gens: usize,
+    arena: ArenaRef,
+}
+
+impl ChainRef {
+    /// Return the raw chain pointer
+    pub fn as_raw(&self) -> mps_chain_t {
+        self.chain.chain
+    }
+
+    /// Number of generations in this chain
+    pub fn generations(&self) -> usize {
+        self.gens
+    }
+
+    /// Access the arena this chain belongs to
+    pub fn arena(&self) -> &ArenaRef {
+        &self.arena
+    }
+}
+
+/// RAII-handle for a raw chain pointer.
+struct RawChain {
+    chain: mps_chain_t,
+}
+
+impl Drop for RawChain {
+    fn drop(&mut self) {
+        unsafe { mps_chain_destroy(self.chain) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::vm::VmArena;
+    use test_util::GENS;
+
+    #[test]
+    fn chain_create_and_drop() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let chain: ChainRef = Chain::new(arena.clone(), GENS).unwrap().into();
+        assert_eq!(chain.arena().as_raw(), arena.as_raw());
+        assert_eq!(chain.generations(), GENS.len());
+    }
+
+    #[test]
+    fn chain_invalid_params() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        assert!(Chain::new(arena.clone(), &[]).is_err());
+
+        let gen = GenParams { capacity_kb: 1024, mortality: 1.5 };
+        assert!(Chain::new(arena, &[gen]).is_err());
+    }
+}
@@ -23,6 +23,8 @@ pub enum Error {
     InsufficientResources,
     InternalLimit,
     InvalidParam,
+    /// An argument was rejected before it was passed to the MPS.
+    InvalidArgument(&'static str),
     Unimplemented,
     Other,
 }
@@ -60,6 +62,7 @@ impl StdError for Error {
             InsufficientResources => "a needed resource could not be obtained.",
             InternalLimit => "an internal limitation was exceeded.",
             InvalidParam => "an invalid parameter was passed.",
+            InvalidArgument(reason) => reason,
             Unimplemented => "operation is not implemented.",
             Other => "operation failed.",
         }
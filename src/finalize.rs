@@ -0,0 +1,182 @@
+//! Finalization of objects
+
+use std::collections::HashMap;
+use std::ptr;
+
+use ffi::{mps_addr_t, mps_arena_t, mps_definalize, mps_finalize, mps_message_discard,
+          mps_message_finalization_ref, mps_message_get, mps_message_t, mps_message_type_disable,
+          mps_message_type_enable};
+use arena::{Arena, ArenaRef};
+use arena::messages::MessageType;
+use errors::{Error, Result};
+
+/// Runs Rust callbacks for objects which have become finalizable.
+///
+/// Objects are registered by address together with a callback. The callback
+/// is run from `Registry::poll` once the collector has found the object to
+/// be dead. Because objects are identified by their address, they should be
+/// allocated in a non-moving pool.
+///
+/// The finalization message keeps the object alive while its callback runs.
+/// The callback may resurrect the object by storing a reference to it in
+/// scanned memory. A finalized object is no longer registered, it has to be
+/// registered again if it should be finalized a second time.
+///
+/// The registry enables finalization messages for its arena, there should
+/// only be one registry per arena. Callbacks which have not run when the
+/// registry is dropped are discarded.
+pub struct Registry {
+    arena: ArenaRef,
+    callbacks: HashMap<mps_addr_t, Box<FnOnce(mps_addr_t)>>,
+}
+
+impl Registry {
+    /// Creates a new registry and enables finalization messages.
+    pub fn new<A: Into<ArenaRef>>(arena: A) -> Self {
+        let arena = arena.into();
+        unsafe { mps_message_type_enable(arena.as_raw(), MessageType::Finalization.as_raw()) }
+
+        Registry {
+            arena: arena,
+            callbacks: HashMap::new(),
+        }
+    }
+
+    /// Registers the object at `addr` for finalization.
+    ///
+    /// Registering an object twice replaces its callback.
+    ///
+    /// See: [`mps_finalize`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/finalization.html#c.mps_finalize)
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point to an object allocated in an automatically managed
+    /// pool of this registry's arena.
+    pub unsafe fn register<F>(&mut self, addr: mps_addr_t, callback: F) -> Result<()>
+    where
+        F: FnOnce(mps_addr_t) + 'static,
+    {
+        if !self.callbacks.contains_key(&addr) {
+            let mut obj = addr;
+            Error::result(mps_finalize(self.arena.as_raw(), &mut obj))?;
+        }
+
+        self.callbacks.insert(addr, Box::new(callback));
+        Ok(())
+    }
+
+    /// Cancels the finalization of the object at `addr`. Returns `false` if
+    /// the object was not registered.
+    ///
+    /// See: [`mps_definalize`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/finalization.html#c.mps_definalize)
+    pub fn cancel(&mut self, addr: mps_addr_t) -> bool {
+        if self.callbacks.remove(&addr).is_none() {
+            return false;
+        }
+
+        let mut obj = addr;
+        unsafe { mps_definalize(self.arena.as_raw(), &mut obj) };
+        true
+    }
+
+    /// Returns true if the object at `addr` is registered for finalization.
+    pub fn is_registered(&self, addr: mps_addr_t) -> bool {
+        self.callbacks.contains_key(&addr)
+    }
+
+    /// Runs the callbacks of all objects which have been finalized since the
+    /// last poll. Returns the number of callbacks run.
+    pub fn poll(&mut self) -> usize {
+        let arena = self.arena.as_raw();
+        let mut count = 0;
+
+        unsafe {
+            let mut message: mps_message_t = ptr::null_mut();
+            while mps_message_get(&mut message, arena, MessageType::Finalization.as_raw()) != 0 {
+                let message = PendingMessage { arena, message };
+                let mut addr: mps_addr_t = ptr::null_mut();
+                mps_message_finalization_ref(&mut addr, arena, message.message);
+
+                if let Some(callback) = self.callbacks.remove(&addr) {
+                    callback(addr);
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Access the arena this registry belongs to
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+}
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        unsafe { mps_message_type_disable(self.arena.as_raw(), MessageType::Finalization.as_raw()) }
+    }
+}
+
+/// Discards a retrieved message on drop, even if a callback panics.
+struct PendingMessage {
+    arena: mps_arena_t,
+    message: mps_message_t,
+}
+
+impl Drop for PendingMessage {
+    fn drop(&mut self) {
+        unsafe { mps_message_discard(self.arena, self.message) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use chain::{Chain, GenParams};
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use pool::lo::LoPool;
+    use test_util::Untagged;
+
+    #[test]
+    fn registry_cancel_unregistered() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let mut registry = Registry::new(arena);
+        let addr = 0x1000 as mps_addr_t;
+        assert!(!registry.is_registered(addr));
+        assert!(!registry.cancel(addr));
+        assert_eq!(registry.poll(), 0);
+    }
+
+    #[test]
+    fn registry_finalize_dead_object() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }]).unwrap();
+        let pool = LoPool::new(fmt, chain).unwrap();
+        let mut ap = pool.ap().unwrap();
+        let mut registry = Registry::new(arena.clone());
+
+        let finalized = Rc::new(Cell::new(None));
+        let addr = {
+            // nothing may be collected before the object is registered
+            let _parked = arena.park();
+            let addr = unsafe { alloc_leaf(&mut ap, 0, b"finalize me").unwrap() };
+            let finalized = finalized.clone();
+            unsafe { registry.register(addr, move |addr| finalized.set(Some(addr))).unwrap() };
+            addr
+        };
+        assert!(registry.is_registered(addr));
+
+        // the object is not referenced from any root
+        arena.collect().unwrap();
+        assert_eq!(registry.poll(), 1);
+        assert_eq!(finalized.get(), Some(addr));
+        assert!(!registry.is_registered(addr));
+    }
+}
@@ -2,10 +2,14 @@
 
 use std::os::raw;
 use std::ptr;
+use std::mem;
+use std::slice;
 
+use ap::AllocationPoint;
 use errors::{Error, Result};
 use fmt::{Format, FormatRef, RawFormat};
 use arena::{Arena, ArenaRef};
+use root::Rank;
 
 use ffi::*;
 
@@ -37,9 +41,25 @@ impl AreaFormat {
     /// Creates a new object format which will be scanned using the built-in
     /// `mps_scan_area_tagged` area scanner.
     pub fn tagged<R: ReferenceTag, A: Into<ArenaRef>>(arena: A) -> Result<Self> {
-        let arena = arena.into();
+        Self::create(arena.into(), Some(obj_scan_tagged::<R>))
+    }
+
+    /// Creates a new object format for objects holding weak references.
+    ///
+    /// Objects are scanned like with `AreaFormat::tagged`, but in objects
+    /// allocated with weak rank, references without an address are replaced
+    /// by zero words afterwards. This makes weak references which have been
+    /// splatted by the collector visible as zeroed slots, regardless of the
+    /// reference tag.
+    pub fn weak_tagged<R: ReferenceTag, A: Into<ArenaRef>>(arena: A) -> Result<Self> {
+        Self::create(arena.into(), Some(obj_scan_weak_tagged::<R>))
+    }
+
+    fn create(arena: ArenaRef, scan: mps_fmt_scan_t) -> Result<Self> {
+        let align = MPS_PF_ALIGN as usize;
         let args = mps_args! {
-            MPS_KEY_FMT_SCAN: Some(obj_scan_tagged::<R>),
+            MPS_KEY_FMT_ALIGN: align,
+            MPS_KEY_FMT_SCAN: scan,
             MPS_KEY_FMT_SKIP: Some(obj_skip),
             MPS_KEY_FMT_FWD: Some(obj_fwd),
             MPS_KEY_FMT_ISFWD: Some(obj_isfwd),
@@ -53,7 +73,7 @@ impl AreaFormat {
         }?;
 
         Ok(AreaFormat {
-            fmt: FormatRef::new(arena, format),
+            fmt: FormatRef::new(arena, format, align),
         })
     }
 }
@@ -70,11 +90,101 @@ impl Into<FormatRef> for AreaFormat {
     }
 }
 
+/// Allocates a leaf object holding a copy of `payload` and returns its
+/// address.
+///
+/// Leaf objects are never scanned, even if they are allocated in a scanned
+/// pool, so the payload may contain arbitrary data. The object is padded to
+/// the platform alignment, its header records the given `class`.
+///
+/// # Safety
+///
+/// `ap` must allocate from a pool using an `AreaFormat`. The object is only
+/// kept alive by references to it, the returned address must be stored in
+/// a scanned location before the collector is allowed to run.
+pub unsafe fn alloc_leaf(ap: &mut AllocationPoint, class: u16, payload: &[u8]) -> Result<mps_addr_t> {
+    let size = object_size(payload.len())?;
+    ap.alloc(size, |addr| write_object(addr, Content::Leaf, class, size, payload))
+}
+
+/// Allocates a leaf object for each of the `payloads`, like `alloc_leaf`.
+/// The objects are committed together, either all of them are allocated or
+/// none is.
+///
+/// # Safety
+///
+/// See `alloc_leaf`.
+pub unsafe fn alloc_leaves(ap: &mut AllocationPoint, class: u16, payloads: &[&[u8]]) -> Result<Vec<mps_addr_t>> {
+    let sizes = payloads
+        .iter()
+        .map(|payload| object_size(payload.len()))
+        .collect::<Result<Vec<_>>>()?;
+    ap.alloc_many(&sizes, |objects| {
+        for ((&addr, &size), payload) in objects.iter().zip(&sizes).zip(payloads) {
+            write_object(addr, Content::Leaf, class, size, payload);
+        }
+    })
+}
+
+/// Allocates an object holding a copy of `words` and returns its address.
+///
+/// Every word matching the reference tag of the format is scanned as a
+/// reference with the rank of the allocation point. Once the referent of a
+/// weak reference dies, its word reads as zero if the format has been
+/// created by `AreaFormat::weak_tagged`.
+///
+/// # Safety
+///
+/// See `alloc_leaf`. Every tagged word must be a valid reference.
+pub unsafe fn alloc_object(ap: &mut AllocationPoint, class: u16, words: &[mps_word_t]) -> Result<mps_addr_t> {
+    let content = match ap.rank() {
+        Some(Rank::Weak) => Content::Weak,
+        _ => Content::Object,
+    };
+    let data = slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * mem::size_of::<mps_word_t>());
+    let size = object_size(data.len())?;
+    ap.alloc(size, |addr| write_object(addr, content, class, size, data))
+}
+
+/// Size of the object holding `len` bytes of data
+fn object_size(len: usize) -> Result<usize> {
+    let header = mem::size_of::<Header>();
+    let align = MPS_PF_ALIGN as usize;
+
+    // the object must be able to hold a forwarding pointer once moved
+    let size = len
+        .max(mem::size_of::<mps_addr_t>())
+        .checked_add(header + align - 1)
+        .map(|size| size & !(align - 1));
+    match size {
+        Some(size) if size <= u32::max_value() as usize => Ok(size),
+        _ => Err(Error::InvalidArgument("object too large")),
+    }
+}
+
+/// Initializes the block at `addr` as an object of `size` bytes
+unsafe fn write_object(addr: mps_addr_t, content: Content, class: u16, size: usize, data: &[u8]) {
+    let header = mem::size_of::<Header>();
+    ptr::write(addr as *mut Header, Header {
+        content: content,
+        _reserved: 0,
+        class: class,
+        length: size as u32,
+    });
+    let body = (addr as *mut Header).offset(1) as *mut u8;
+    ptr::copy_nonoverlapping(data.as_ptr(), body, data.len());
+    ptr::write_bytes(body.offset(data.len() as isize), 0, size - header - data.len());
+}
+
+#[derive(Clone, Copy)]
 #[repr(u8)]
 enum Content {
     Padding = 0,
     Forward = 1,
     Object = 2,
+    Leaf = 3,
+    /// An object allocated with weak rank
+    Weak = 4,
 }
 
 #[repr(C, packed)]
@@ -106,7 +216,7 @@ unsafe extern "C" fn obj_scan_tagged<R: ReferenceTag>(
         let obj_base = obj.offset(1) as mps_addr_t;
         let obj_limit = obj_skip(base);
 
-        if let Content::Object = (*obj).content {
+        if let Content::Object | Content::Weak = (*obj).content {
             let res = mps_scan_area_tagged(ss, obj_base, obj_limit, closure);
             if res != MPS_RES_OK as mps_res_t {
                 return res;
@@ -120,6 +230,38 @@ unsafe extern "C" fn obj_scan_tagged<R: ReferenceTag>(
     MPS_RES_OK as mps_res_t
 }
 
+unsafe extern "C" fn obj_scan_weak_tagged<R: ReferenceTag>(
+    ss: mps_ss_t,
+    base: mps_addr_t,
+    limit: mps_addr_t,
+) -> mps_res_t {
+    let res = obj_scan_tagged::<R>(ss, base, limit);
+    if res != MPS_RES_OK as mps_res_t || R::PATTERN == 0 {
+        return res;
+    }
+
+    // splatted weak references keep their tag, clear them completely
+    let mut base = base;
+    while base < limit {
+        let obj = base as *mut Header;
+        let obj_limit = obj_skip(base);
+
+        if let Content::Weak = (*obj).content {
+            let mut slot = obj.offset(1) as *mut mps_word_t;
+            while (slot as mps_addr_t) < obj_limit {
+                if *slot == R::PATTERN {
+                    *slot = 0;
+                }
+                slot = slot.offset(1);
+            }
+        }
+
+        base = obj_limit;
+    }
+
+    MPS_RES_OK as mps_res_t
+}
+
 unsafe extern "C" fn obj_skip(base: mps_addr_t) -> mps_addr_t {
     let obj = base as *mut Header;
     return base.offset((*obj).length as isize);
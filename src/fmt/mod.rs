@@ -3,7 +3,7 @@
 use std::sync::Arc;
 
 use ffi::{mps_fmt_destroy, mps_fmt_t};
-use arena::{Arena, ArenaRef};
+use arena::ArenaRef;
 
 pub mod area;
 
@@ -18,22 +18,30 @@ pub trait Format {
 /// will be kept alive.
 #[derive(Clone)]
 pub struct FormatRef {
-    arena: ArenaRef,
+    // fields are dropped in order, the format must go before its arena
     fmt: Arc<Format>,
+    arena: ArenaRef,
+    align: usize,
 }
 
 impl FormatRef {
-    fn new<F: Format + 'static>(arena: ArenaRef, fmt: F) -> Self {
+    fn new<F: Format + 'static>(arena: ArenaRef, fmt: F, align: usize) -> Self {
         FormatRef {
-            arena: arena,
             fmt: Arc::new(fmt),
+            arena: arena,
+            align: align,
         }
     }
 
     /// Access the arena this format belongs to
-    pub fn arena(&self) -> &Arena {
+    pub fn arena(&self) -> &ArenaRef {
         &self.arena
     }
+
+    /// Alignment of the objects of this format
+    pub fn align(&self) -> usize {
+        self.align
+    }
 }
 
 impl Format for FormatRef {
@@ -5,6 +5,13 @@ pub mod arena;
 pub mod pool;
 pub mod errors;
 pub mod fmt;
+pub mod chain;
 pub mod thread;
 pub mod root;
 pub mod handle;
+pub mod finalize;
+pub mod ap;
+pub mod alloc;
+
+#[cfg(test)]
+mod test_util;
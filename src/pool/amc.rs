@@ -0,0 +1,231 @@
+//! Automatic Mostly-Copying pool
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use ap::AllocationPoint;
+use chain::ChainRef;
+use errors::{Error, Result};
+use fmt::FormatRef;
+use ffi::{mps_bool_t, mps_class_amc, mps_pool_class_t, mps_pool_t};
+use pool::{FormattedPool, Pool, PoolRef};
+use root::Rank;
+
+/// A moving, generational garbage collected pool.
+///
+/// Objects are described by an object format and may only contain exact
+/// references. New objects are allocated in the first generation of the
+/// pool's chain and promoted along it.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/amc.html)
+/// for details.
+pub struct AmcPool {
+    pool: PoolRef,
+}
+
+impl AmcPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<F: Into<FormatRef>, C: Into<ChainRef>>(fmt: F, chain: C) -> Result<Self> {
+        Self::builder().build(fmt, chain)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> AmcPoolBuilder {
+        AmcPoolBuilder::new()
+    }
+
+    /// Creates a new allocation point. Objects in this pool can only
+    /// contain exact references, any other rank is rejected.
+    pub fn ap(&self, rank: Rank) -> Result<AllocationPoint> {
+        if rank != Rank::Exact {
+            return Err(Error::InvalidArgument("AMC allocation points only support exact references"));
+        }
+
+        // AMC buffers have no rank keyword, they are always exact
+        AllocationPoint::new(self.pool.clone(), None)
+    }
+}
+
+impl Pool for AmcPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for AmcPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for AMC and AMCZ pools, `P` is the type of the pool built.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly. The generation new objects are allocated in
+/// is not configurable, the MPS always uses the first one of the chain.
+pub struct AmcPoolBuilder<P = AmcPool> {
+    interior: Option<bool>,
+    extend_by: Option<usize>,
+    large_size: Option<usize>,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<P> AmcPoolBuilder<P> {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        AmcPoolBuilder {
+            interior: None,
+            extend_by: None,
+            large_size: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Whether ambiguous interior pointers keep objects alive
+    /// (`MPS_KEY_INTERIOR`).
+    pub fn interior(mut self, interior: bool) -> Self {
+        self.interior = Some(interior);
+        self
+    }
+
+    /// Size of the segments the pool requests from the arena
+    /// (`MPS_KEY_EXTEND_BY`).
+    pub fn extend_by(mut self, size: usize) -> Self {
+        self.extend_by = Some(size);
+        self
+    }
+
+    /// Objects of at least this size are allocated in their own segment
+    /// (`MPS_KEY_LARGE_SIZE`).
+    pub fn large_size(mut self, size: usize) -> Self {
+        self.large_size = Some(size);
+        self
+    }
+
+    /// Validates the parameters and creates a pool of the given class
+    pub(crate) fn create(&self, class: mps_pool_class_t, fmt: FormatRef, chain: ChainRef) -> Result<PoolRef> {
+        if self.extend_by == Some(0) || self.large_size == Some(0) {
+            return Err(Error::InvalidArgument("AMC sizes must be nonzero"));
+        }
+
+        let mut args = Vec::new();
+        if let Some(interior) = self.interior {
+            args.push(mps_arg_s!(MPS_KEY_INTERIOR, interior as mps_bool_t));
+        }
+        if let Some(size) = self.extend_by {
+            args.push(mps_arg_s!(MPS_KEY_EXTEND_BY, size));
+        }
+        if let Some(size) = self.large_size {
+            args.push(mps_arg_s!(MPS_KEY_LARGE_SIZE, size));
+        }
+
+        FormattedPool::create(class, fmt, Some(chain), None, args)
+    }
+}
+
+impl AmcPoolBuilder<AmcPool> {
+    /// Validates the parameters and creates the pool. The format and chain
+    /// must belong to the same arena, they are kept alive by the pool.
+    pub fn build<F: Into<FormatRef>, C: Into<ChainRef>>(&self, fmt: F, chain: C) -> Result<AmcPool> {
+        let class = unsafe { mps_class_amc() };
+        self.create(class, fmt.into(), chain.into()).map(|pool| AmcPool { pool })
+    }
+}
+
+impl<P> Default for AmcPoolBuilder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P> Clone for AmcPoolBuilder<P> {
+    fn clone(&self) -> Self {
+        AmcPoolBuilder {
+            interior: self.interior,
+            extend_by: self.extend_by,
+            large_size: self.large_size,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P> fmt::Debug for AmcPoolBuilder<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AmcPoolBuilder")
+            .field("interior", &self.interior)
+            .field("extend_by", &self.extend_by)
+            .field("large_size", &self.large_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+    use ap::AllocPattern;
+    use arena::{Arena, ArenaRef};
+    use arena::vm::VmArena;
+    use chain::Chain;
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use test_util::{Untagged, GENS};
+
+    #[test]
+    fn amc_create_ap() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena, GENS).unwrap();
+        let pool = AmcPool::builder()
+            .interior(false)
+            .extend_by(1 << 16)
+            .build(fmt, chain)
+            .unwrap();
+
+        let ap = pool.ap(Rank::Exact).unwrap();
+        assert_eq!(ap.pool().as_raw(), pool.as_raw());
+        assert!(pool.ap(Rank::Weak).is_err());
+    }
+
+    #[test]
+    fn amc_foreign_chain() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let other: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena).unwrap();
+        let chain = Chain::new(other, GENS).unwrap();
+        assert!(AmcPool::new(fmt, chain).is_err());
+    }
+
+    #[test]
+    fn amc_nested_ramp_patterns() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), GENS).unwrap();
+        let pool = AmcPool::new(fmt, chain).unwrap();
+
+        // the objects are never used, they may die in the collection
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+        {
+            let mut outer = ap.begin_pattern(AllocPattern::Ramp).unwrap();
+            unsafe { alloc_leaf(&mut outer, 0, b"outer").unwrap() };
+            {
+                let mut inner = outer.begin_pattern(AllocPattern::RampCollectAll).unwrap();
+                for _ in 0..1000 {
+                    unsafe { alloc_leaf(&mut inner, 0, &[0; 256]).unwrap() };
+                }
+                arena.collect().unwrap();
+                unsafe { alloc_leaf(&mut inner, 0, b"inner").unwrap() };
+                inner.end().unwrap();
+            }
+
+            mem::forget(outer.begin_pattern(AllocPattern::Ramp).unwrap());
+            outer.reset_patterns().unwrap();
+            // a pattern begun after the reset is not ended by the outer guard
+            let newer = outer.begin_pattern(AllocPattern::Ramp).unwrap();
+            newer.end().unwrap();
+            assert!(outer.end().is_err());
+        }
+        unsafe { alloc_leaf(&mut ap, 0, b"after").unwrap() };
+        assert!(ap.begin_pattern(AllocPattern::Ramp).unwrap().end().is_ok());
+    }
+}
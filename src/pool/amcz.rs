@@ -0,0 +1,97 @@
+//! Automatic Mostly-Copying Zero-rank pool
+
+use ap::AllocationPoint;
+use chain::ChainRef;
+use errors::Result;
+use fmt::FormatRef;
+use ffi::{mps_class_amcz, mps_pool_t};
+use pool::{Pool, PoolRef};
+use pool::amc::AmcPoolBuilder;
+
+/// A moving, generational garbage collected pool for leaf objects.
+///
+/// Objects in this pool must not contain references, they are never
+/// scanned. This makes it suitable for strings, byte arrays or boxed
+/// numbers. Use `fmt::area::alloc_leaf` to allocate objects of an
+/// `AreaFormat`.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/amcz.html)
+/// for details.
+pub struct AmczPool {
+    pool: PoolRef,
+}
+
+impl AmczPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<F: Into<FormatRef>, C: Into<ChainRef>>(fmt: F, chain: C) -> Result<Self> {
+        AmczPoolBuilder::new().build(fmt, chain)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> AmczPoolBuilder {
+        AmczPoolBuilder::new()
+    }
+
+    /// Creates a new allocation point. Objects in this pool contain no
+    /// references, so it has no rank.
+    pub fn ap(&self) -> Result<AllocationPoint> {
+        AllocationPoint::new(self.pool.clone(), None)
+    }
+}
+
+impl Pool for AmczPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for AmczPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for AMCZ pools, which take the same parameters as AMC pools.
+pub type AmczPoolBuilder = AmcPoolBuilder<AmczPool>;
+
+impl AmcPoolBuilder<AmczPool> {
+    /// Validates the parameters and creates the pool. The format and chain
+    /// must belong to the same arena, they are kept alive by the pool.
+    pub fn build<F: Into<FormatRef>, C: Into<ChainRef>>(&self, fmt: F, chain: C) -> Result<AmczPool> {
+        let class = unsafe { mps_class_amcz() };
+        self.create(class, fmt.into(), chain.into()).map(|pool| AmczPool { pool })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use arena::ArenaRef;
+    use arena::vm::VmArena;
+    use chain::{Chain, GenParams};
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use test_util::Untagged;
+
+    #[test]
+    fn amcz_alloc_leaf() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }]).unwrap();
+        let pool = AmczPool::new(fmt, chain).unwrap();
+        let mut ap = pool.ap().unwrap();
+
+        // nothing refers to the objects, keep the collector from running
+        let _parked = arena.park();
+        let objects: Vec<_> = ["", "leaf", "a somewhat longer string payload"]
+            .iter()
+            .map(|s| (s, unsafe { alloc_leaf(&mut ap, 7, s.as_bytes()).unwrap() }))
+            .collect();
+
+        for (s, addr) in objects {
+            let payload = unsafe { slice::from_raw_parts((addr as *const u64).offset(1) as *const u8, s.len()) };
+            assert_eq!(payload, s.as_bytes());
+        }
+    }
+}
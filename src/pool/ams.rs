@@ -0,0 +1,196 @@
+//! Automatic Mark and Sweep pool
+
+use std::os::raw;
+
+use ap::AllocationPoint;
+use chain::ChainRef;
+use errors::{Error, Result};
+use fmt::FormatRef;
+use ffi::{mps_bool_t, mps_class_ams, mps_class_ams_debug, mps_pool_t};
+use pool::{DebugOptions, FormattedPool, Pool, PoolRef};
+use root::Rank;
+
+/// A non-moving garbage collected pool.
+///
+/// Objects in this pool never move, so their addresses can be handed to
+/// foreign code or be kept alive by ambiguous references.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/ams.html)
+/// for details.
+pub struct AmsPool {
+    pool: PoolRef,
+    support_ambiguous: bool,
+}
+
+impl AmsPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<F: Into<FormatRef>, C: Into<ChainRef>>(fmt: F, chain: C) -> Result<Self> {
+        AmsPoolBuilder::new().build(fmt, chain)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> AmsPoolBuilder {
+        AmsPoolBuilder::new()
+    }
+
+    /// Creates a new allocation point for objects containing references of
+    /// the given rank. Only exact references are supported, and ambiguous
+    /// ones if the pool supports ambiguous references, any other rank is
+    /// rejected.
+    pub fn ap(&self, rank: Rank) -> Result<AllocationPoint> {
+        if rank == Rank::Weak {
+            return Err(Error::InvalidArgument("AMS allocation points do not support weak references"));
+        }
+        if rank == Rank::Ambiguous && !self.support_ambiguous {
+            return Err(Error::InvalidArgument("AMS pool does not support ambiguous references"));
+        }
+
+        AllocationPoint::new(self.pool.clone(), Some(rank))
+    }
+}
+
+impl Pool for AmsPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for AmsPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for AMS pools.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly.
+#[derive(Debug, Clone, Default)]
+pub struct AmsPoolBuilder {
+    gen: Option<usize>,
+    support_ambiguous: Option<bool>,
+    debug: Option<DebugOptions>,
+}
+
+impl AmsPoolBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Generation of the chain new objects are allocated in, at most the
+    /// number of generations in the chain (`MPS_KEY_GEN`).
+    pub fn gen(mut self, gen: usize) -> Self {
+        self.gen = Some(gen);
+        self
+    }
+
+    /// Whether objects may be kept alive by ambiguous references, and may
+    /// be allocated with ambiguous rank. Enabled by default
+    /// (`MPS_KEY_AMS_SUPPORT_AMBIGUOUS`).
+    pub fn support_ambiguous(mut self, support: bool) -> Self {
+        self.support_ambiguous = Some(support);
+        self
+    }
+
+    /// Use the debugging variant of the pool with the given options
+    /// (`MPS_KEY_POOL_DEBUG_OPTIONS`).
+    pub fn debug(mut self, options: DebugOptions) -> Self {
+        self.debug = Some(options);
+        self
+    }
+
+    /// Validates the parameters and creates the pool. The format and chain
+    /// must belong to the same arena, they are kept alive by the pool.
+    pub fn build<F: Into<FormatRef>, C: Into<ChainRef>>(&self, fmt: F, chain: C) -> Result<AmsPool> {
+        let chain = chain.into();
+
+        let mut args = Vec::new();
+        if let Some(gen) = self.gen {
+            if gen > chain.generations() {
+                return Err(Error::InvalidArgument("AMS generation exceeds the chain"));
+            }
+            args.push(mps_arg_s!(MPS_KEY_GEN, gen as raw::c_uint));
+        }
+        if let Some(support) = self.support_ambiguous {
+            args.push(mps_arg_s!(MPS_KEY_AMS_SUPPORT_AMBIGUOUS, support as mps_bool_t));
+        }
+
+        let class = unsafe {
+            match self.debug {
+                Some(_) => mps_class_ams_debug(),
+                None => mps_class_ams(),
+            }
+        };
+        FormattedPool::create(class, fmt.into(), Some(chain), self.debug.clone(), args).map(|pool| AmsPool {
+            pool: pool,
+            // the MPS supports ambiguous references by default
+            support_ambiguous: self.support_ambiguous.unwrap_or(true),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::ArenaRef;
+    use arena::vm::VmArena;
+    use chain::Chain;
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use test_util::{Untagged, GENS};
+
+    #[test]
+    fn ams_alloc_exact_and_ambiguous() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), GENS).unwrap();
+        let pool = AmsPool::builder()
+            .gen(1)
+            .support_ambiguous(true)
+            .build(fmt, chain)
+            .unwrap();
+
+        let _parked = arena.park();
+        for &rank in &[Rank::Exact, Rank::Ambiguous] {
+            let mut ap = pool.ap(rank).unwrap();
+            let addr = unsafe { alloc_leaf(&mut ap, 0, b"pinned").unwrap() };
+            assert!(!addr.is_null());
+        }
+        assert!(pool.ap(Rank::Weak).is_err());
+    }
+
+    #[test]
+    fn ams_reject_ambiguous() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena, GENS).unwrap();
+        let pool = AmsPool::builder().support_ambiguous(false).build(fmt, chain).unwrap();
+        assert!(pool.ap(Rank::Ambiguous).is_err());
+        assert!(pool.ap(Rank::Exact).is_ok());
+    }
+
+    #[test]
+    fn ams_debug_fenceposts() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), GENS).unwrap();
+        let pool = AmsPool::builder()
+            .debug(DebugOptions::default())
+            .build(fmt, chain)
+            .unwrap();
+
+        let _parked = arena.park();
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+        unsafe { alloc_leaf(&mut ap, 0, b"fenced").unwrap() };
+        pool.check_fenceposts();
+        pool.check_free_space();
+    }
+
+    #[test]
+    fn ams_invalid_gen() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena, GENS).unwrap();
+        assert!(AmsPool::builder().gen(3).build(fmt, chain).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Automatic Weak Linked pool
+
+use std::os::raw;
+
+use ap::AllocationPoint;
+use chain::ChainRef;
+use errors::{Error, Result};
+use fmt::FormatRef;
+use ffi::{mps_addr_t, mps_awl_find_dependent_t, mps_class_awl, mps_pool_t};
+use pool::{FormattedPool, Pool, PoolRef};
+use root::Rank;
+
+/// A non-moving garbage collected pool for objects with weak references,
+/// such as weak hash tables.
+///
+/// Weak references to dead objects are set to zero by the collector. Use
+/// `fmt::area::AreaFormat::weak_tagged` to have them show up as zero words
+/// for tagged references as well.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/awl.html)
+/// for details.
+pub struct AwlPool {
+    pool: PoolRef,
+}
+
+/// Finds the dependent object of an object in an AWL pool.
+///
+/// A weak table usually consists of two objects, e.g. one holding the keys
+/// and one holding the values. The collector might have to access the
+/// dependent object when it splats a reference in the other one. Refer to
+/// the Memory Pool System reference about
+/// [dependent objects](https://www.ravenbrook.com/project/mps/master/manual/html/pool/awl.html#dependent-objects)
+/// for details.
+pub trait FindDependent {
+    /// Returns the address of the object depending on the object at `addr`,
+    /// or null if there is none.
+    ///
+    /// This is called by the collector, it must not allocate, access any
+    /// other object in automatically managed memory, or panic.
+    fn find_dependent(addr: mps_addr_t) -> mps_addr_t;
+}
+
+impl AwlPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<F: Into<FormatRef>, C: Into<ChainRef>>(fmt: F, chain: C) -> Result<Self> {
+        AwlPoolBuilder::new().build(fmt, chain)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> AwlPoolBuilder {
+        AwlPoolBuilder::new()
+    }
+
+    /// Creates a new allocation point for objects containing exact or weak
+    /// references, ambiguous references are rejected.
+    pub fn ap(&self, rank: Rank) -> Result<AllocationPoint> {
+        if rank == Rank::Ambiguous {
+            return Err(Error::InvalidArgument("AWL allocation points only support exact or weak references"));
+        }
+
+        AllocationPoint::new(self.pool.clone(), Some(rank))
+    }
+}
+
+impl Pool for AwlPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for AwlPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for AWL pools.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly. Without a `FindDependent` implementation
+/// objects have no dependent object.
+#[derive(Debug, Clone, Default)]
+pub struct AwlPoolBuilder {
+    gen: Option<usize>,
+    find_dependent: mps_awl_find_dependent_t,
+}
+
+impl AwlPoolBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Generation of the chain new objects are allocated in, at most the
+    /// number of generations in the chain (`MPS_KEY_GEN`).
+    pub fn gen(mut self, gen: usize) -> Self {
+        self.gen = Some(gen);
+        self
+    }
+
+    /// Use `D` to find the dependent objects (`MPS_KEY_AWL_FIND_DEPENDENT`).
+    pub fn find_dependent<D: FindDependent>(mut self) -> Self {
+        self.find_dependent = Some(find_dependent::<D>);
+        self
+    }
+
+    /// Validates the parameters and creates the pool. The format and chain
+    /// must belong to the same arena, they are kept alive by the pool.
+    pub fn build<F: Into<FormatRef>, C: Into<ChainRef>>(&self, fmt: F, chain: C) -> Result<AwlPool> {
+        let chain = chain.into();
+
+        let mut args = Vec::new();
+        if let Some(gen) = self.gen {
+            if gen > chain.generations() {
+                return Err(Error::InvalidArgument("AWL generation exceeds the chain"));
+            }
+            args.push(mps_arg_s!(MPS_KEY_GEN, gen as raw::c_uint));
+        }
+        if self.find_dependent.is_some() {
+            args.push(mps_arg_s!(MPS_KEY_AWL_FIND_DEPENDENT, self.find_dependent));
+        }
+
+        let class = unsafe { mps_class_awl() };
+        FormattedPool::create(class, fmt.into(), Some(chain), None, args).map(|pool| AwlPool { pool })
+    }
+}
+
+unsafe extern "C" fn find_dependent<D: FindDependent>(addr: mps_addr_t) -> mps_addr_t {
+    D::find_dependent(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::slice;
+
+    use super::*;
+    use arena::{Arena, ArenaRef};
+    use arena::vm::VmArena;
+    use chain::{Chain, GenParams};
+    use ffi::{mps_root_create_area_tagged, mps_root_destroy, mps_root_t, mps_scan_area_tagged, mps_word_t};
+    use fmt::area::{alloc_leaf, alloc_object, AreaFormat, ReferenceTag};
+    use pool::lo::LoPool;
+
+    struct LowBit;
+
+    impl ReferenceTag for LowBit {
+        const MASK: u64 = 0b1;
+        const PATTERN: u64 = 0b1;
+    }
+
+    struct NoDependent;
+
+    impl FindDependent for NoDependent {
+        fn find_dependent(_addr: mps_addr_t) -> mps_addr_t {
+            ptr::null_mut()
+        }
+    }
+
+    #[test]
+    fn awl_weak_ap() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::weak_tagged::<LowBit, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }]).unwrap();
+        let pool = AwlPool::builder()
+            .gen(0)
+            .find_dependent::<NoDependent>()
+            .build(fmt, chain)
+            .unwrap();
+        assert!(pool.ap(Rank::Ambiguous).is_err());
+
+        let _parked = arena.park();
+        let mut ap = pool.ap(Rank::Weak).unwrap();
+        let addr = unsafe { alloc_leaf(&mut ap, 0, b"weak").unwrap() };
+        assert!(!addr.is_null());
+    }
+
+    #[test]
+    fn awl_weak_reference_splatted() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt: FormatRef = AreaFormat::weak_tagged::<LowBit, _>(arena.clone()).unwrap().into();
+        let chain: ChainRef = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }])
+            .unwrap()
+            .into();
+        let weak_pool = AwlPool::builder()
+            .find_dependent::<NoDependent>()
+            .build(fmt.clone(), chain.clone())
+            .unwrap();
+        let leaf_pool = LoPool::new(fmt, chain).unwrap();
+
+        // keeps the weak object and one of its referents alive
+        let mut roots: [mps_word_t; 2] = [0; 2];
+        let root = unsafe {
+            let mut root: mps_root_t = ptr::null_mut();
+            let base = roots.as_mut_ptr();
+            let res = mps_root_create_area_tagged(
+                &mut root,
+                arena.as_raw(),
+                Rank::Exact.as_raw(),
+                0,
+                base as *mut _,
+                base.offset(2) as *mut _,
+                Some(mps_scan_area_tagged),
+                LowBit::MASK,
+                LowBit::PATTERN,
+            );
+            Error::result(res).map(|_| root).unwrap()
+        };
+
+        let weak = {
+            let _parked = arena.park();
+            let mut leaf_ap = leaf_pool.ap().unwrap();
+            let mut weak_ap = weak_pool.ap(Rank::Weak).unwrap();
+            unsafe {
+                let live = alloc_leaf(&mut leaf_ap, 0, b"live").unwrap() as mps_word_t | 1;
+                let dead = alloc_leaf(&mut leaf_ap, 0, b"dead").unwrap() as mps_word_t | 1;
+                let weak = alloc_object(&mut weak_ap, 0, &[live, dead, 42 << 1]).unwrap();
+                roots[0] = weak as mps_word_t | 1;
+                roots[1] = live;
+                weak
+            }
+        };
+
+        arena.collect().unwrap();
+        let words = unsafe { slice::from_raw_parts((weak as *const mps_word_t).offset(1), 3) };
+        assert_eq!(words, &[roots[1], 0, 42 << 1]);
+        unsafe { mps_root_destroy(root) };
+    }
+}
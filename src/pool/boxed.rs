@@ -0,0 +1,142 @@
+//! Single values allocated in manual pools
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
+
+use errors::Result;
+use pool::{alloc_array, free_array, ManualAllocPool};
+
+/// A value allocated in a manually managed pool.
+///
+/// The value is dropped and its memory freed on drop. Zero-sized values do
+/// not allocate. The box can be sent to other threads if the value can, and
+/// the pool can be shared between threads.
+pub struct PoolBox<'pool, T, P: ManualAllocPool + 'pool> {
+    pool: &'pool P,
+    ptr: *mut T,
+    _marker: PhantomData<T>,
+}
+
+impl<'pool, T, P: ManualAllocPool> PoolBox<'pool, T, P> {
+    /// Moves `value` into newly allocated memory of `pool`
+    pub fn new(pool: &'pool P, value: T) -> Result<Self> {
+        unsafe {
+            let ptr = alloc_array(pool, 1)?;
+            ptr::write(ptr, value);
+
+            Ok(PoolBox {
+                pool: pool,
+                ptr: ptr,
+                _marker: PhantomData,
+            })
+        }
+    }
+
+    /// Moves the value out of the box, freeing its memory
+    pub fn into_inner(self) -> T {
+        unsafe {
+            let value = ptr::read(self.ptr);
+            free_array(self.pool, self.ptr, 1);
+            mem::forget(self);
+            value
+        }
+    }
+
+    /// Access the pool this value is allocated in
+    pub fn pool(&self) -> &'pool P {
+        self.pool
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> Drop for PoolBox<'pool, T, P> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr);
+            free_array(self.pool, self.ptr, 1);
+        }
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> Deref for PoolBox<'pool, T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> DerefMut for PoolBox<'pool, T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'pool, T: fmt::Debug, P: ManualAllocPool> fmt::Debug for PoolBox<'pool, T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<'pool, T: Send, P: ManualAllocPool + Sync> Send for PoolBox<'pool, T, P> {}
+unsafe impl<'pool, T: Sync, P: ManualAllocPool + Sync> Sync for PoolBox<'pool, T, P> {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use pool::Pool;
+    use pool::mvff::MvffPool;
+
+    #[test]
+    fn pool_box_drop() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let value = Rc::new([1u64; 16]);
+        {
+            let mut boxed = PoolBox::new(&pool, value.clone()).unwrap();
+            assert_eq!(Rc::strong_count(&value), 2);
+            *boxed = Rc::new([2; 16]);
+            assert_eq!(Rc::strong_count(&value), 1);
+            assert_eq!(boxed[15], 2);
+        }
+
+        let boxed = PoolBox::new(&pool, value.clone()).unwrap();
+        assert!(Rc::ptr_eq(&boxed.into_inner(), &value));
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn pool_box_zero_sized() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let total = pool.total_size();
+        let boxed = PoolBox::new(&pool, ()).unwrap();
+        assert_eq!(*boxed, ());
+        assert_eq!(pool.total_size(), total);
+    }
+
+    #[test]
+    fn pool_box_over_aligned() {
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::builder().align(64).build(arena).unwrap();
+        let boxed = PoolBox::new(&pool, Aligned(1)).unwrap();
+        assert_eq!(&*boxed as *const _ as usize % 64, 0);
+
+        let pool = MvffPool::new(VmArena::with_capacity(64 << 20).unwrap()).unwrap();
+        assert!(PoolBox::new(&pool, Aligned(1)).is_err());
+    }
+
+    #[test]
+    fn pool_box_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<PoolBox<'static, u64, MvffPool>>();
+    }
+}
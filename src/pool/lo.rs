@@ -0,0 +1,121 @@
+//! Leaf Object pool
+
+use std::os::raw;
+
+use ap::AllocationPoint;
+use chain::ChainRef;
+use errors::{Error, Result};
+use fmt::FormatRef;
+use ffi::{mps_class_lo, mps_pool_t};
+use pool::{FormattedPool, Pool, PoolRef};
+
+/// A non-moving garbage collected pool for leaf objects.
+///
+/// Objects in this pool must not contain references, they are neither
+/// scanned nor moved. This makes it suitable for large buffers whose
+/// address must stay stable. Use `fmt::area::alloc_leaf` to allocate
+/// objects of an `AreaFormat`.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/lo.html)
+/// for details.
+pub struct LoPool {
+    pool: PoolRef,
+}
+
+impl LoPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<F: Into<FormatRef>, C: Into<ChainRef>>(fmt: F, chain: C) -> Result<Self> {
+        LoPoolBuilder::new().build(fmt, chain)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> LoPoolBuilder {
+        LoPoolBuilder::new()
+    }
+
+    /// Creates a new allocation point. Objects in this pool contain no
+    /// references, so it has no rank.
+    pub fn ap(&self) -> Result<AllocationPoint> {
+        AllocationPoint::new(self.pool.clone(), None)
+    }
+}
+
+impl Pool for LoPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for LoPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for LO pools.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly.
+#[derive(Debug, Clone, Default)]
+pub struct LoPoolBuilder {
+    gen: Option<usize>,
+}
+
+impl LoPoolBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Generation of the chain new objects are allocated in, at most the
+    /// number of generations in the chain (`MPS_KEY_GEN`).
+    pub fn gen(mut self, gen: usize) -> Self {
+        self.gen = Some(gen);
+        self
+    }
+
+    /// Validates the parameters and creates the pool. The format and chain
+    /// must belong to the same arena, they are kept alive by the pool.
+    pub fn build<F: Into<FormatRef>, C: Into<ChainRef>>(&self, fmt: F, chain: C) -> Result<LoPool> {
+        let chain = chain.into();
+
+        let mut args = Vec::new();
+        if let Some(gen) = self.gen {
+            if gen > chain.generations() {
+                return Err(Error::InvalidArgument("LO generation exceeds the chain"));
+            }
+            args.push(mps_arg_s!(MPS_KEY_GEN, gen as raw::c_uint));
+        }
+
+        let class = unsafe { mps_class_lo() };
+        FormattedPool::create(class, fmt.into(), Some(chain), None, args).map(|pool| LoPool { pool })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use arena::ArenaRef;
+    use arena::vm::VmArena;
+    use chain::{Chain, GenParams};
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use test_util::Untagged;
+
+    #[test]
+    fn lo_alloc_large_leaf() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena.clone()).unwrap();
+        let chain = Chain::new(arena.clone(), &[GenParams { capacity_kb: 1024, mortality: 0.8 }]).unwrap();
+        let pool = LoPool::builder().gen(1).build(fmt, chain).unwrap();
+        let mut ap = pool.ap().unwrap();
+
+        let _parked = arena.park();
+        let image = vec![0xab; 1 << 20];
+        let addr = unsafe { alloc_leaf(&mut ap, 0, &image).unwrap() };
+        let payload = unsafe { slice::from_raw_parts((addr as *const u64).offset(1) as *const u8, image.len()) };
+        assert_eq!(payload, &image[..]);
+        assert!(pool.total_size() >= image.len());
+    }
+}
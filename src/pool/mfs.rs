@@ -1,8 +1,239 @@
 //! Manual Fixed Size pool
 
-use pool::{Pool, PoolRef};
+use std::mem;
+use std::ptr;
+use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
 
+use arena::{Arena, ArenaRef};
+use errors::{Error, Result};
+use ffi::{mps_addr_t, mps_alloc, mps_class_mfs, mps_free, mps_pool_t, MPS_PF_ALIGN};
+use pool::{ManualAllocPool, Pool, PoolRef, RawPool};
+
+/// A manually managed pool of fixed-size units.
+///
+/// Every allocation from this pool must be exactly `unit_size` bytes.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/mfs.html)
+/// for details.
 pub struct MfsPool {
     size: usize,
     pool: PoolRef,
 }
+
+impl MfsPool {
+    /// Creates a new pool handing out units of `unit_size` bytes
+    pub fn new<A: Into<ArenaRef>>(arena: A, unit_size: usize) -> Result<Self> {
+        Self::create(arena.into(), unit_size, None)
+    }
+
+    /// Creates a new pool which requests memory in blocks of `extend_by`
+    /// bytes from the arena
+    pub fn with_extend_by<A: Into<ArenaRef>>(arena: A, unit_size: usize, extend_by: usize) -> Result<Self> {
+        Self::create(arena.into(), unit_size, Some(extend_by))
+    }
+
+    fn create(arena: ArenaRef, unit_size: usize, extend_by: Option<usize>) -> Result<Self> {
+        if unit_size == 0 {
+            return Err(Error::InvalidArgument("MFS unit size must be nonzero"));
+        }
+
+        let mut args = vec![mps_arg_s!(MPS_KEY_MFS_UNIT_SIZE, unit_size)];
+        if let Some(extend_by) = extend_by {
+            if extend_by < unit_size {
+                return Err(Error::InvalidArgument("MFS extend size must not be smaller than the unit size"));
+            }
+            args.push(mps_arg_s!(MPS_KEY_EXTEND_BY, extend_by));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let pool = unsafe { RawPool::create(arena.as_raw(), mps_class_mfs(), args.as_mut_ptr()) }?;
+
+        Ok(MfsPool {
+            size: unit_size,
+            pool: PoolRef::new(arena, pool, MPS_PF_ALIGN as usize),
+        })
+    }
+
+    /// Size of the units allocated from this pool
+    pub fn unit_size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Pool for MfsPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl ManualAllocPool for MfsPool {
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size != self.size {
+            return Err(Error::InvalidArgument("MFS allocations must match the unit size"));
+        }
+
+        Ok(())
+    }
+}
+
+unsafe impl Sync for MfsPool {}
+
+impl Into<PoolRef> for MfsPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// A typed MFS pool storing values of type `T`.
+pub struct FixedPool<T> {
+    pool: MfsPool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> FixedPool<T> {
+    /// Creates a new pool for values of type `T`
+    pub fn new<A: Into<ArenaRef>>(arena: A) -> Result<Self> {
+        Self::check_layout()?;
+        MfsPool::new(arena, Self::unit_size()).map(|pool| FixedPool {
+            pool,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Creates a new pool for values of type `T` which requests memory in
+    /// blocks of `extend_by` bytes from the arena
+    pub fn with_extend_by<A: Into<ArenaRef>>(arena: A, extend_by: usize) -> Result<Self> {
+        Self::check_layout()?;
+        MfsPool::with_extend_by(arena, Self::unit_size(), extend_by).map(|pool| FixedPool {
+            pool,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Moves `value` into a newly allocated unit
+    pub fn alloc<'pool>(&'pool self, value: T) -> Result<FixedBox<'pool, T>> {
+        let pool = self.pool.as_raw();
+        let size = self.pool.unit_size();
+        let addr = unsafe {
+            let mut addr: mps_addr_t = ptr::null_mut();
+            Error::result(mps_alloc(&mut addr, pool, size))?;
+            ptr::write(addr as *mut T, value);
+            addr
+        };
+
+        Ok(FixedBox {
+            pool: pool,
+            addr: addr as *mut T,
+            size: size,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Access the underlying untyped pool
+    pub fn pool(&self) -> &MfsPool {
+        &self.pool
+    }
+
+    fn unit_size() -> usize {
+        // units must at least be able to hold a free list pointer
+        mem::size_of::<T>().max(mem::size_of::<mps_addr_t>())
+    }
+
+    fn check_layout() -> Result<()> {
+        // units are aligned to the platform alignment
+        if mem::align_of::<T>() > MPS_PF_ALIGN as usize {
+            return Err(Error::InvalidArgument("type alignment exceeds the MFS unit alignment"));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Pool for FixedPool<T> {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+/// A value allocated in a `FixedPool`.
+///
+/// The value is dropped and its unit freed on drop.
+pub struct FixedBox<'pool, T: 'pool> {
+    pool: mps_pool_t,
+    addr: *mut T,
+    size: usize,
+    _marker: PhantomData<&'pool mut T>,
+}
+
+impl<'pool, T> Drop for FixedBox<'pool, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.addr);
+            mps_free(self.pool, self.addr as mps_addr_t, self.size)
+        }
+    }
+}
+
+impl<'pool, T> Deref for FixedBox<'pool, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.addr }
+    }
+}
+
+impl<'pool, T> DerefMut for FixedBox<'pool, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.addr }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::vm::VmArena;
+
+    #[derive(Default)]
+    struct Frame {
+        pc: usize,
+        locals: [u64; 4],
+    }
+
+    #[test]
+    fn mfs_alloc_chunk() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MfsPool::new(arena, mem::size_of::<Frame>()).unwrap();
+        {
+            let chunk = pool.alloc::<Frame>(1).unwrap();
+            assert_eq!(chunk[0].pc, 0);
+            assert!(pool.alloc::<Frame>(2).is_err());
+        }
+        assert!(pool.total_size() >= mem::size_of::<Frame>());
+    }
+
+    #[test]
+    fn mfs_invalid_params() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        assert!(MfsPool::new(arena.clone(), 0).is_err());
+        assert!(MfsPool::with_extend_by(arena, 1024, 512).is_err());
+    }
+
+    #[test]
+    fn fixed_box_alloc_and_drop() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = FixedPool::<Frame>::with_extend_by(arena, 1 << 16).unwrap();
+        let mut frames = Vec::new();
+        for i in 0..1000 {
+            let mut frame = pool.alloc(Frame::default()).unwrap();
+            frame.pc = i;
+            frame.locals[3] = i as u64;
+            frames.push(frame);
+        }
+        assert!(frames.iter().enumerate().all(|(i, f)| f.pc == i && f.locals[3] == i as u64));
+
+        let free = pool.free_size();
+        frames.clear();
+        assert!(pool.free_size() > free);
+    }
+}
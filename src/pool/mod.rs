@@ -1,37 +1,66 @@
 //! Memory pool implementation and interfaces
 
 pub mod mfs;
+pub mod mvff;
+pub mod mvt;
+pub mod amc;
+pub mod amcz;
+pub mod ams;
+pub mod awl;
+pub mod lo;
+pub mod snc;
+pub mod sac;
+pub mod boxed;
+pub mod vec;
 
 use std::sync::Arc;
 use std::ptr;
 use std::slice;
-use std::mem;
+use std::mem::{self, MaybeUninit};
 use std::ops::{Deref, DerefMut};
 use std::marker::PhantomData;
 
 use arena::{Arena, ArenaRef};
+use chain::ChainRef;
 use errors::{Error, Result};
-use ffi::{mps_addr_t, mps_alloc, mps_free, mps_pool_destroy, mps_pool_free_size, mps_pool_t, mps_pool_total_size};
+use fmt::{Format, FormatRef};
+use ffi::{mps_addr_t, mps_alloc, mps_arena_t, mps_arg_s, mps_free, mps_pool_check_fenceposts,
+          mps_pool_check_free_space, mps_pool_class_t, mps_pool_create_k, mps_pool_debug_option_s, mps_pool_destroy,
+          mps_pool_free_size, mps_pool_t, mps_pool_total_size, MPS_PF_ALIGN};
 
 /// Clone-able handle to a type-erased object pool.
 #[derive(Clone)]
 pub struct PoolRef {
-    arena: ArenaRef,
+    // fields are dropped in order, the pool must go before its arena
     pool: Arc<Pool>,
+    arena: ArenaRef,
+    align: usize,
 }
 
 impl PoolRef {
-    fn new<P: Pool + 'static>(arena: ArenaRef, pool: P) -> Self {
+    fn new<P: Pool + 'static>(arena: ArenaRef, pool: P, align: usize) -> Self {
         PoolRef {
-            arena: arena,
             pool: Arc::new(pool),
+            arena: arena,
+            align: align,
         }
     }
 
-    /// Access the arena this format belongs to
-    pub fn arena(&self) -> &Arena {
+    /// Access the arena this pool belongs to
+    pub fn arena(&self) -> &ArenaRef {
         &self.arena
     }
+
+    /// Alignment of the blocks allocated from this pool
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl Pool for PoolRef {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
 }
 
 /// Generic pool interface
@@ -47,6 +76,62 @@ pub trait Pool {
     fn free_size(&self) -> usize {
         unsafe { mps_pool_free_size(self.as_raw()) }
     }
+
+    /// Checks the fenceposts around all allocated blocks. A corrupted
+    /// fencepost makes the MPS assert and abort the process. This does
+    /// nothing unless the pool has been created with `DebugOptions`.
+    ///
+    /// See: [`mps_pool_check_fenceposts`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/debugging.html#c.mps_pool_check_fenceposts)
+    fn check_fenceposts(&self) {
+        unsafe { mps_pool_check_fenceposts(self.as_raw()) }
+    }
+
+    /// Checks that the free space of the pool has not been written to. A
+    /// modification makes the MPS assert and abort the process. This does
+    /// nothing unless the pool has been created with `DebugOptions`.
+    ///
+    /// See: [`mps_pool_check_free_space`](https://www.ravenbrook.com/project/mps/master/manual/html/topic/debugging.html#c.mps_pool_check_free_space)
+    fn check_free_space(&self) {
+        unsafe { mps_pool_check_free_space(self.as_raw()) }
+    }
+}
+
+/// Options for the debugging variant of a pool.
+///
+/// The templates are copied in a repeating pattern onto the fenceposts
+/// around every allocated block, and onto free space. An empty template
+/// disables the respective check. The default options use the MPS defaults
+/// `POST` and `DEAD`.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/debugging.html)
+/// for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugOptions {
+    /// Template for the fenceposts
+    pub fence_template: Vec<u8>,
+    /// Template for free space
+    pub free_template: Vec<u8>,
+}
+
+impl DebugOptions {
+    /// Returns the raw options, which point into the templates
+    fn as_raw(&self) -> mps_pool_debug_option_s {
+        mps_pool_debug_option_s {
+            fence_template: self.fence_template.as_ptr() as *const _,
+            fence_size: self.fence_template.len(),
+            free_template: self.free_template.as_ptr() as *const _,
+            free_size: self.free_template.len(),
+        }
+    }
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        DebugOptions {
+            fence_template: b"POST".to_vec(),
+            free_template: b"DEAD".to_vec(),
+        }
+    }
 }
 
 /// A manually allocated chunk of fixed-size, homogenous memory
@@ -59,31 +144,92 @@ pub struct Chunk<'pool, T: 'pool> {
     _marker: PhantomData<&'pool mut T>,
 }
 
+/// Pools whose blocks are allocated with `mps_alloc` and freed manually.
+///
+/// The MPS serializes all operations on a pool through the lock of its
+/// arena, so the pools implementing this trait are `Sync`. The chunks, boxes
+/// and vectors allocated from them can be sent to other threads.
 pub trait ManualAllocPool: Pool {
+    /// Checks whether a block of `size` bytes can be allocated from this pool.
+    fn check_size(&self, _size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Alignment of the blocks allocated from this pool
+    fn align(&self) -> usize {
+        MPS_PF_ALIGN as usize
+    }
+
+    /// Allocates `len` values initialized to their default
     fn alloc<'pool, T: Default>(&'pool self, len: usize) -> Result<Chunk<'pool, T>> {
-        // TODO(gandro): check len fits in isize and is nonzero
-        let pool = self.as_raw();
-        let addr = unsafe {
-            // allocate
-            let mut addr: mps_addr_t = ptr::null_mut();
-            let size = len * mem::size_of::<T>();
-            Error::result(mps_alloc(&mut addr, pool, size))?;
-
-            // initialize with default value
-            let base: *mut T = addr as *mut _;
-            for i in 0..len as isize {
-                ptr::write(base.offset(i), Default::default());
-            }
+        self.alloc_with(len, |_| Default::default())
+    }
 
-            addr
-        };
+    /// Allocates `len` uninitialized values
+    fn alloc_uninit<'pool, T>(&'pool self, len: usize) -> Result<Chunk<'pool, MaybeUninit<T>>> {
+        unsafe {
+            let base: *mut MaybeUninit<T> = alloc_array(self, len)?;
+            Ok(Chunk::from_raw(self.as_raw(), base, len))
+        }
+    }
+
+    /// Allocates `len` values whose memory is set to zero. Use
+    /// `Chunk::assume_init` if all zero bytes are a valid value of `T`.
+    fn alloc_zeroed<'pool, T>(&'pool self, len: usize) -> Result<Chunk<'pool, MaybeUninit<T>>> {
+        let chunk = self.alloc_uninit(len)?;
+        unsafe { ptr::write_bytes(chunk.addr as *mut MaybeUninit<T>, 0, len) };
+        Ok(chunk)
+    }
+
+    /// Allocates `len` values, initializing the value at index `i` to
+    /// `init(i)`
+    fn alloc_with<'pool, T, F>(&'pool self, len: usize, mut init: F) -> Result<Chunk<'pool, T>>
+    where
+        F: FnMut(usize) -> T,
+    {
+        let mut i = 0;
+        fill_array(self, len, || {
+            let value = init(i);
+            i += 1;
+            Some(value)
+        })
+    }
+
+    /// Allocates the values yielded by `iter`. Fails if the iterator
+    /// yields fewer values than its reported length, additional values are
+    /// ignored.
+    fn alloc_from_iter<'pool, T, I>(&'pool self, iter: I) -> Result<Chunk<'pool, T>>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let mut iter = iter.into_iter();
+        let len = iter.len();
+        fill_array(self, len, || iter.next())
+    }
+}
 
-        Ok(Chunk {
+impl<'pool, T> Chunk<'pool, T> {
+    unsafe fn from_raw(pool: mps_pool_t, base: *mut T, len: usize) -> Self {
+        Chunk {
             pool: pool,
-            addr: addr,
+            addr: base as mps_addr_t,
             len: len,
             _marker: PhantomData,
-        })
+        }
+    }
+}
+
+impl<'pool, T> Chunk<'pool, MaybeUninit<T>> {
+    /// Converts the chunk into one of initialized values.
+    ///
+    /// # Safety
+    ///
+    /// All values of the chunk must have been initialized.
+    pub unsafe fn assume_init(self) -> Chunk<'pool, T> {
+        let chunk = Chunk::from_raw(self.pool, self.addr as *mut T, self.len);
+        mem::forget(self);
+        chunk
     }
 }
 
@@ -96,7 +242,9 @@ impl<'pool, T> Drop for Chunk<'pool, T> {
             }
 
             let size = self.len * mem::size_of::<T>();
-            mps_free(self.pool, self.addr, size)
+            if size != 0 {
+                mps_free(self.pool, self.addr, size)
+            }
         }
     }
 }
@@ -123,13 +271,253 @@ impl<'pool, T> DerefMut for Chunk<'pool, T> {
 
 
 
+/// Size in bytes of an array of `len` values of type `T`
+fn array_size<T>(len: usize) -> Result<usize> {
+    match mem::size_of::<T>().checked_mul(len) {
+        Some(size) if size <= isize::max_value() as usize => Ok(size),
+        _ => Err(Error::InvalidArgument("allocation size overflows")),
+    }
+}
+
+/// Allocates uninitialized memory for `len` values of type `T`. Arrays of
+/// size zero are not allocated from the pool, a dangling pointer is
+/// returned instead.
+unsafe fn alloc_array<P: ManualAllocPool + ?Sized, T>(pool: &P, len: usize) -> Result<*mut T> {
+    let size = array_size::<T>(len)?;
+    if size == 0 {
+        return Ok(ptr::NonNull::dangling().as_ptr());
+    }
+    if mem::align_of::<T>() > pool.align() {
+        return Err(Error::InvalidArgument("type alignment exceeds the pool alignment"));
+    }
+    pool.check_size(size)?;
+
+    let mut addr: mps_addr_t = ptr::null_mut();
+    Error::result(mps_alloc(&mut addr, pool.as_raw(), size))?;
+    Ok(addr as *mut T)
+}
+
+/// Allocates `len` values and initializes them in order with `next`. If
+/// `next` fails or panics, the values initialized so far are dropped and
+/// the memory is freed.
+fn fill_array<'pool, P, T, F>(pool: &'pool P, len: usize, mut next: F) -> Result<Chunk<'pool, T>>
+where
+    P: ManualAllocPool + ?Sized,
+    F: FnMut() -> Option<T>,
+{
+    struct Partial<'a, P: ManualAllocPool + ?Sized + 'a, T> {
+        pool: &'a P,
+        base: *mut T,
+        len: usize,
+        init: usize,
+    }
+
+    impl<'a, P: ManualAllocPool + ?Sized, T> Drop for Partial<'a, P, T> {
+        fn drop(&mut self) {
+            unsafe {
+                ptr::drop_in_place(slice::from_raw_parts_mut(self.base, self.init));
+                free_array(self.pool, self.base, self.len);
+            }
+        }
+    }
+
+    let mut partial = Partial {
+        pool: pool,
+        base: unsafe { alloc_array::<P, T>(pool, len)? },
+        len: len,
+        init: 0,
+    };
+
+    while partial.init < len {
+        let value = next().ok_or(Error::InvalidArgument("iterator yielded fewer values than its length"))?;
+        unsafe { ptr::write(partial.base.offset(partial.init as isize), value) };
+        partial.init += 1;
+    }
+
+    let chunk = unsafe { Chunk::from_raw(pool.as_raw(), partial.base, len) };
+    mem::forget(partial);
+    Ok(chunk)
+}
+
+/// Frees memory obtained from `alloc_array` with the same length
+unsafe fn free_array<P: ManualAllocPool + ?Sized, T>(pool: &P, addr: *mut T, len: usize) {
+    let size = len * mem::size_of::<T>();
+    if size != 0 {
+        mps_free(pool.as_raw(), addr as mps_addr_t, size)
+    }
+}
+
 /// RAII-style handle
 struct RawPool {
     pool: mps_pool_t,
 }
 
+impl RawPool {
+    /// Creates a new pool of the given class.
+    unsafe fn create(arena: mps_arena_t, class: mps_pool_class_t, args: *mut mps_arg_s) -> Result<Self> {
+        let mut pool: mps_pool_t = ptr::null_mut();
+        let res = mps_pool_create_k(&mut pool, arena, class, args);
+        Error::result(res).map(|_| RawPool { pool })
+    }
+}
+
+impl Pool for RawPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool
+    }
+}
+
 impl Drop for RawPool {
     fn drop(&mut self) {
         unsafe { mps_pool_destroy(self.pool) }
     }
 }
+
+/// RAII-style handle for a pool whose objects are described by a format.
+struct FormattedPool {
+    // fields are dropped in order, the pool must go before its format and chain
+    pool: RawPool,
+    _fmt: FormatRef,
+    _chain: Option<ChainRef>,
+}
+
+impl FormattedPool {
+    /// Creates a new pool of the given class, passing on the format, chain
+    /// and debug options in addition to `args`. All of them are kept alive
+    /// by the pool.
+    fn create(
+        class: mps_pool_class_t,
+        fmt: FormatRef,
+        chain: Option<ChainRef>,
+        debug: Option<DebugOptions>,
+        mut args: Vec<mps_arg_s>,
+    ) -> Result<PoolRef> {
+        let arena = fmt.arena().clone();
+        let align = fmt.align();
+
+        let mut raw_debug = debug.as_ref().map(DebugOptions::as_raw);
+        if let Some(ref mut raw_debug) = raw_debug {
+            args.push(mps_arg_s!(MPS_KEY_POOL_DEBUG_OPTIONS, raw_debug));
+        }
+
+        args.push(mps_arg_s!(MPS_KEY_FORMAT, fmt.as_raw()));
+        if let Some(ref chain) = chain {
+            if chain.arena().as_raw() != arena.as_raw() {
+                return Err(Error::InvalidArgument("format and chain belong to different arenas"));
+            }
+            args.push(mps_arg_s!(MPS_KEY_CHAIN, chain.as_raw()));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let pool = unsafe { RawPool::create(arena.as_raw(), class, args.as_mut_ptr()) }?;
+
+        let pool = FormattedPool {
+            pool: pool,
+            _fmt: fmt,
+            _chain: chain,
+        };
+
+        Ok(match debug {
+            Some(options) => PoolRef::new(arena, DebugPool::new(pool, options), align),
+            None => PoolRef::new(arena, pool, align),
+        })
+    }
+}
+
+impl Pool for FormattedPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+/// RAII-style handle for a debugging pool, the MPS refers to the templates
+/// of its options for the whole lifetime of the pool.
+struct DebugPool<P: Pool> {
+    // fields are dropped in order, the pool must go before its templates
+    pool: P,
+    _options: DebugOptions,
+}
+
+impl<P: Pool> DebugPool<P> {
+    fn new(pool: P, options: DebugOptions) -> Self {
+        DebugPool {
+            pool: pool,
+            _options: options,
+        }
+    }
+}
+
+impl<P: Pool> Pool for DebugPool<P> {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::rc::Rc;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use pool::mvff::MvffPool;
+
+    #[test]
+    fn chunk_alloc_uninit_and_zeroed() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+
+        let mut chunk = pool.alloc_uninit::<u32>(16).unwrap();
+        for (i, value) in chunk.iter_mut().enumerate() {
+            *value = MaybeUninit::new(i as u32);
+        }
+        let chunk = unsafe { chunk.assume_init() };
+        assert_eq!(chunk[15], 15);
+
+        let chunk = unsafe { pool.alloc_zeroed::<u64>(100).unwrap().assume_init() };
+        assert!(chunk.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn chunk_alloc_with_and_from_iter() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+
+        let squares = pool.alloc_with(10, |i| i * i).unwrap();
+        assert_eq!(squares[9], 81);
+
+        let chunk = pool.alloc_from_iter(squares.iter().map(|&v| v + 1)).unwrap();
+        assert_eq!(&chunk[..3], &[1, 2, 5]);
+
+        let empty = pool.alloc_from_iter(Vec::<String>::new()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn chunk_alloc_with_panic() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let value = Rc::new(0);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            pool.alloc_with(10, |i| {
+                assert!(i < 5);
+                value.clone()
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn chunk_invalid_layout() {
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        assert!(pool.alloc_uninit::<Aligned>(1).is_err());
+        assert!(pool.alloc_uninit::<u64>(usize::max_value() / 2).is_err());
+        assert!(pool.alloc::<u8>(0).unwrap().is_empty());
+    }
+}
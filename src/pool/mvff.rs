@@ -0,0 +1,295 @@
+//! Manual Variable First Fit pool
+
+use std::mem;
+
+use arena::{Arena, ArenaRef};
+use errors::{Error, Result};
+use ffi::{mps_addr_t, mps_bool_t, mps_class_mvff, mps_class_mvff_debug, mps_pool_t, MPS_PF_ALIGN};
+use pool::{DebugOptions, DebugPool, ManualAllocPool, Pool, PoolRef, RawPool};
+
+/// Default size of the blocks the MPS requests from the arena
+const MVFF_EXTEND_BY_DEFAULT: usize = 65536;
+/// Default predicted mean block size of the MPS
+const MVFF_AVG_SIZE_DEFAULT: usize = 32;
+
+/// A general-purpose manually managed pool.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/mvff.html)
+/// for details.
+pub struct MvffPool {
+    align: usize,
+    pool: PoolRef,
+}
+
+impl MvffPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<A: Into<ArenaRef>>(arena: A) -> Result<Self> {
+        MvffPoolBuilder::new().build(arena)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> MvffPoolBuilder {
+        MvffPoolBuilder::new()
+    }
+
+    /// Alignment of the blocks allocated from this pool
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl Pool for MvffPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl ManualAllocPool for MvffPool {
+    fn align(&self) -> usize {
+        self.align
+    }
+}
+
+unsafe impl Sync for MvffPool {}
+
+impl Into<PoolRef> for MvffPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for MVFF pools.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly.
+#[derive(Debug, Clone, Default)]
+pub struct MvffPoolBuilder {
+    extend_by: Option<usize>,
+    mean_size: Option<usize>,
+    align: Option<usize>,
+    spare: Option<f64>,
+    arena_high: Option<bool>,
+    slot_high: Option<bool>,
+    first_fit: Option<bool>,
+    debug: Option<DebugOptions>,
+}
+
+impl MvffPoolBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Size of the blocks the pool requests from the arena
+    /// (`MPS_KEY_EXTEND_BY`).
+    pub fn extend_by(mut self, size: usize) -> Self {
+        self.extend_by = Some(size);
+        self
+    }
+
+    /// Predicted mean size of allocated blocks (`MPS_KEY_MEAN_SIZE`).
+    pub fn mean_size(mut self, size: usize) -> Self {
+        self.mean_size = Some(size);
+        self
+    }
+
+    /// Alignment of allocated blocks, must be a power of two and at least
+    /// the size of a pointer (`MPS_KEY_ALIGN`).
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Maximum proportion of memory the pool keeps spare, between 0 and 1
+    /// (`MPS_KEY_SPARE`).
+    pub fn spare(mut self, spare: f64) -> Self {
+        self.spare = Some(spare);
+        self
+    }
+
+    /// Acquire new segments from the high end of the arena's address
+    /// space instead of the low end (`MPS_KEY_MVFF_ARENA_HIGH`).
+    pub fn arena_high(mut self, high: bool) -> Self {
+        self.arena_high = Some(high);
+        self
+    }
+
+    /// Allocate from the high end of the free area which was found instead
+    /// of its low end, so blocks are allocated towards high addresses
+    /// (`MPS_KEY_MVFF_SLOT_HIGH`). Only affects `mps_alloc`, allocation
+    /// points always use a worst-fit policy.
+    pub fn slot_high(mut self, high: bool) -> Self {
+        self.slot_high = Some(high);
+        self
+    }
+
+    /// Use the first free area which fits, searching from low addresses,
+    /// instead of the last one (`MPS_KEY_MVFF_FIRST_FIT`). Only affects
+    /// `mps_alloc`, allocation points always use a worst-fit policy.
+    ///
+    /// Setting `arena_high` and `slot_high`, and clearing `first_fit`
+    /// gives a first-fit allocator working from the top of memory down.
+    pub fn first_fit(mut self, first_fit: bool) -> Self {
+        self.first_fit = Some(first_fit);
+        self
+    }
+
+    /// Use the debugging variant of the pool with the given options
+    /// (`MPS_KEY_POOL_DEBUG_OPTIONS`).
+    pub fn debug(mut self, options: DebugOptions) -> Self {
+        self.debug = Some(options);
+        self
+    }
+
+    /// Validates the parameters and creates the pool
+    pub fn build<A: Into<ArenaRef>>(&self, arena: A) -> Result<MvffPool> {
+        let arena = arena.into();
+        self.validate(&arena)?;
+
+        let mut args = Vec::new();
+        if let Some(size) = self.extend_by {
+            args.push(mps_arg_s!(MPS_KEY_EXTEND_BY, size));
+        }
+        if let Some(size) = self.mean_size {
+            args.push(mps_arg_s!(MPS_KEY_MEAN_SIZE, size));
+        }
+        if let Some(align) = self.align {
+            args.push(mps_arg_s!(MPS_KEY_ALIGN, align));
+        }
+        if let Some(spare) = self.spare {
+            args.push(mps_arg_s!(MPS_KEY_SPARE, spare));
+        }
+        if let Some(high) = self.arena_high {
+            args.push(mps_arg_s!(MPS_KEY_MVFF_ARENA_HIGH, high as mps_bool_t));
+        }
+        if let Some(high) = self.slot_high {
+            args.push(mps_arg_s!(MPS_KEY_MVFF_SLOT_HIGH, high as mps_bool_t));
+        }
+        if let Some(first_fit) = self.first_fit {
+            args.push(mps_arg_s!(MPS_KEY_MVFF_FIRST_FIT, first_fit as mps_bool_t));
+        }
+        // the MPS keeps pointing into the templates, so the pool takes this
+        // very copy of the options
+        let debug = self.debug.clone();
+        let mut raw_debug = debug.as_ref().map(DebugOptions::as_raw);
+        if let Some(ref mut raw_debug) = raw_debug {
+            args.push(mps_arg_s!(MPS_KEY_POOL_DEBUG_OPTIONS, raw_debug));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let pool = unsafe {
+            let class = match debug {
+                Some(_) => mps_class_mvff_debug(),
+                None => mps_class_mvff(),
+            };
+            RawPool::create(arena.as_raw(), class, args.as_mut_ptr())
+        }?;
+
+        let align = self.align.unwrap_or(MPS_PF_ALIGN as usize);
+        Ok(MvffPool {
+            align: align,
+            pool: match debug {
+                Some(options) => PoolRef::new(arena, DebugPool::new(pool, options), align),
+                None => PoolRef::new(arena, pool, align),
+            },
+        })
+    }
+
+    fn validate(&self, arena: &ArenaRef) -> Result<()> {
+        if let Some(align) = self.align {
+            if !align.is_power_of_two() || align < mem::size_of::<mps_addr_t>() {
+                return Err(Error::InvalidArgument("MVFF alignment must be a power of two of at least pointer size"));
+            }
+            if align > arena.grain_size() {
+                return Err(Error::InvalidArgument("MVFF alignment must not exceed the arena grain size"));
+            }
+        }
+        if let Some(spare) = self.spare {
+            if !(spare >= 0.0 && spare <= 1.0) {
+                return Err(Error::InvalidArgument("MVFF spare proportion must be between 0 and 1"));
+            }
+        }
+        if self.extend_by == Some(0) || self.mean_size == Some(0) {
+            return Err(Error::InvalidArgument("MVFF sizes must be nonzero"));
+        }
+
+        // the MPS requires this, defaults included
+        let extend_by = self.extend_by.unwrap_or(MVFF_EXTEND_BY_DEFAULT);
+        let mean_size = self.mean_size.unwrap_or(MVFF_AVG_SIZE_DEFAULT);
+        if mean_size > extend_by {
+            return Err(Error::InvalidArgument("MVFF mean size must not exceed the extend size"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::vm::VmArena;
+
+    #[test]
+    fn mvff_alloc_chunk() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let mut chunk = pool.alloc::<u64>(100).unwrap();
+        chunk[99] = 42;
+        assert_eq!(chunk.iter().sum::<u64>(), 42);
+    }
+
+    #[test]
+    fn mvff_builder() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::builder()
+            .extend_by(1 << 20)
+            .mean_size(64)
+            .align(16)
+            .spare(0.5)
+            .arena_high(true)
+            .slot_high(true)
+            .first_fit(false)
+            .build(arena)
+            .unwrap();
+        assert_eq!(pool.align(), 16);
+
+        let chunk = pool.alloc::<u8>(1000).unwrap();
+        assert_eq!(chunk.as_ptr() as usize % 16, 0);
+    }
+
+    #[test]
+    fn mvff_debug_fenceposts() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let options = DebugOptions {
+            fence_template: b"fencepost".to_vec(),
+            free_template: b"free".to_vec(),
+        };
+        let pool = MvffPool::builder().debug(options).build(arena).unwrap();
+        {
+            let mut chunk = pool.alloc::<u8>(100).unwrap();
+            for byte in chunk.iter_mut() {
+                *byte = 0xff;
+            }
+            pool.check_fenceposts();
+        }
+        pool.check_free_space();
+    }
+
+    #[test]
+    fn mvff_invalid_params() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        assert!(MvffPool::builder().align(12).build(arena.clone()).is_err());
+        assert!(MvffPool::builder().spare(2.0).build(arena.clone()).is_err());
+        assert!(MvffPool::builder().mean_size(1 << 20).build(arena.clone()).is_err());
+        assert!(MvffPool::builder().extend_by(16).build(arena.clone()).is_err());
+        assert!(MvffPool::builder().mean_size(1 << 20).extend_by(1 << 20).build(arena).is_ok());
+    }
+
+    #[test]
+    fn mvff_align_exceeds_grain() {
+        let arena = VmArena::builder().size(64 << 20).grain_size(1 << 16).build().unwrap();
+        let arena: ArenaRef = arena.into();
+        assert!(MvffPool::builder().align(1 << 16).build(arena.clone()).is_ok());
+        assert!(MvffPool::builder().align(1 << 17).build(arena).is_err());
+    }
+}
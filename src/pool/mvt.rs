@@ -0,0 +1,286 @@
+//! Manual Variable Temporal pool
+
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+
+use ap::AllocationPoint;
+use arena::{Arena, ArenaRef};
+use errors::{Error, Result};
+use ffi::{mps_addr_t, mps_class_mvt, mps_pool_t, mps_word_t, MPS_PF_ALIGN};
+use pool::{array_size, Chunk, Pool, PoolRef, RawPool};
+
+/// Default predicted mean block size of the MPS
+const MVT_MEAN_SIZE_DEFAULT: usize = 32;
+/// Default predicted maximum block size of the MPS
+const MVT_MAX_SIZE_DEFAULT: usize = 8192;
+
+/// A manually managed pool for blocks with a predictable size and a short,
+/// temporal lifetime, such as buffers.
+///
+/// The pool does not support `mps_alloc`, so it is no `ManualAllocPool`.
+/// Chunks are allocated through an allocation point instead, and freed
+/// when they are dropped.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/mvt.html)
+/// for details.
+pub struct MvtPool {
+    align: usize,
+    pool: PoolRef,
+}
+
+impl MvtPool {
+    /// Creates a new pool with the default parameters
+    pub fn new<A: Into<ArenaRef>>(arena: A) -> Result<Self> {
+        MvtPoolBuilder::new().build(arena)
+    }
+
+    /// Returns a builder to configure all pool creation parameters
+    pub fn builder() -> MvtPoolBuilder {
+        MvtPoolBuilder::new()
+    }
+
+    /// Alignment of the blocks allocated from this pool
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Creates a new allocation point for this pool
+    pub fn ap(&self) -> Result<AllocationPoint> {
+        AllocationPoint::new(self.pool.clone(), None)
+    }
+
+    /// Allocates `len` values initialized to their default through `ap`
+    pub fn alloc<'pool, T: Default>(&'pool self, ap: &mut AllocationPoint, len: usize) -> Result<Chunk<'pool, T>> {
+        let mut chunk = self.alloc_uninit(ap, len)?;
+        for value in chunk.iter_mut() {
+            *value = MaybeUninit::new(T::default());
+        }
+        Ok(unsafe { chunk.assume_init() })
+    }
+
+    /// Allocates `len` uninitialized values through `ap`. Like all chunks,
+    /// the memory is freed once the chunk is dropped.
+    pub fn alloc_uninit<'pool, T>(
+        &'pool self,
+        ap: &mut AllocationPoint,
+        len: usize,
+    ) -> Result<Chunk<'pool, MaybeUninit<T>>> {
+        if ap.pool().as_raw() != self.as_raw() {
+            return Err(Error::InvalidArgument("allocation point belongs to another pool"));
+        }
+        if mem::align_of::<T>() > self.align {
+            return Err(Error::InvalidArgument("type alignment exceeds the pool alignment"));
+        }
+
+        let size = array_size::<T>(len)?;
+        let base = if size == 0 {
+            ptr::NonNull::dangling().as_ptr()
+        } else {
+            // the pool rounds the size up again when the chunk is freed
+            let mask = self.align - 1;
+            let size = size.checked_add(mask).ok_or(Error::InvalidArgument("allocation size overflows"))? & !mask;
+            // blocks of manual pools need no initialization
+            unsafe { ap.alloc(size, |_| ())? as *mut MaybeUninit<T> }
+        };
+
+        Ok(unsafe { Chunk::from_raw(self.as_raw(), base, len) })
+    }
+}
+
+impl Pool for MvtPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for MvtPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+/// Builder for MVT pools.
+///
+/// Every parameter is optional, the MPS defaults are used for all parameters
+/// which are not set explicitly. The size parameters are hints, the pool
+/// will still work if they are wrong, only less efficiently.
+#[derive(Debug, Clone, Default)]
+pub struct MvtPoolBuilder {
+    align: Option<usize>,
+    min_size: Option<usize>,
+    mean_size: Option<usize>,
+    max_size: Option<usize>,
+    reserve_depth: Option<usize>,
+    frag_limit: Option<f64>,
+}
+
+impl MvtPoolBuilder {
+    /// Creates a new builder with all parameters set to their defaults
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Alignment of allocated blocks, must be a power of two and at least
+    /// the size of a pointer (`MPS_KEY_ALIGN`).
+    pub fn align(mut self, align: usize) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    /// Predicted minimum size of allocated blocks (`MPS_KEY_MIN_SIZE`).
+    pub fn min_size(mut self, size: usize) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// Predicted mean size of allocated blocks (`MPS_KEY_MEAN_SIZE`).
+    pub fn mean_size(mut self, size: usize) -> Self {
+        self.mean_size = Some(size);
+        self
+    }
+
+    /// Predicted maximum size of allocated blocks (`MPS_KEY_MAX_SIZE`).
+    pub fn max_size(mut self, size: usize) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Number of mean sized blocks the pool keeps storage for after they
+    /// have been freed (`MPS_KEY_MVT_RESERVE_DEPTH`).
+    pub fn reserve_depth(mut self, depth: usize) -> Self {
+        self.reserve_depth = Some(depth);
+        self
+    }
+
+    /// Maximum proportion of fragmentation tolerated before the pool
+    /// switches to a first fit policy, between 0 and 1
+    /// (`MPS_KEY_MVT_FRAG_LIMIT`).
+    pub fn frag_limit(mut self, limit: f64) -> Self {
+        self.frag_limit = Some(limit);
+        self
+    }
+
+    /// Validates the parameters and creates the pool
+    pub fn build<A: Into<ArenaRef>>(&self, arena: A) -> Result<MvtPool> {
+        self.validate()?;
+
+        let mut args = Vec::new();
+        if let Some(align) = self.align {
+            args.push(mps_arg_s!(MPS_KEY_ALIGN, align));
+        }
+        if let Some(size) = self.min_size {
+            args.push(mps_arg_s!(MPS_KEY_MIN_SIZE, size));
+        }
+        if let Some(size) = self.mean_size {
+            args.push(mps_arg_s!(MPS_KEY_MEAN_SIZE, size));
+        }
+        if let Some(size) = self.max_size {
+            args.push(mps_arg_s!(MPS_KEY_MAX_SIZE, size));
+        }
+        if let Some(depth) = self.reserve_depth {
+            args.push(mps_arg_s!(MPS_KEY_MVT_RESERVE_DEPTH, depth as mps_word_t));
+        }
+        if let Some(limit) = self.frag_limit {
+            args.push(mps_arg_s!(MPS_KEY_MVT_FRAG_LIMIT, limit));
+        }
+        args.push(mps_arg_s!(MPS_KEY_ARGS_END));
+
+        let arena = arena.into();
+        let pool = unsafe { RawPool::create(arena.as_raw(), mps_class_mvt(), args.as_mut_ptr()) }?;
+
+        let align = self.align.unwrap_or(MPS_PF_ALIGN as usize);
+        Ok(MvtPool {
+            align: align,
+            pool: PoolRef::new(arena, pool, align),
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(align) = self.align {
+            if !align.is_power_of_two() || align < mem::size_of::<mps_addr_t>() {
+                return Err(Error::InvalidArgument("MVT alignment must be a power of two of at least pointer size"));
+            }
+        }
+
+        // the MPS requires these to be ordered, defaults included
+        let min = self.min_size.unwrap_or(MPS_PF_ALIGN as usize);
+        let mean = self.mean_size.unwrap_or(MVT_MEAN_SIZE_DEFAULT);
+        let max = self.max_size.unwrap_or(MVT_MAX_SIZE_DEFAULT);
+        if !(0 < min && min <= mean && mean <= max) {
+            return Err(Error::InvalidArgument("MVT sizes must satisfy 0 < min <= mean <= max"));
+        }
+
+        if self.reserve_depth == Some(0) {
+            return Err(Error::InvalidArgument("MVT reserve depth must be nonzero"));
+        }
+        if let Some(limit) = self.frag_limit {
+            if !(limit >= 0.0 && limit <= 1.0) {
+                return Err(Error::InvalidArgument("MVT fragmentation limit must be between 0 and 1"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arena::vm::VmArena;
+
+    #[test]
+    fn mvt_alloc_chunks() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvtPool::builder()
+            .min_size(64)
+            .mean_size(4096)
+            .max_size(1 << 16)
+            .reserve_depth(16)
+            .frag_limit(0.5)
+            .build(arena)
+            .unwrap();
+        let mut ap = pool.ap().unwrap();
+
+        let mut buffers = Vec::new();
+        for i in 0..16 {
+            let mut buf = pool.alloc::<u8>(&mut ap, 64 << (i % 8)).unwrap();
+            for byte in buf.iter_mut() {
+                *byte = i as u8;
+            }
+            buffers.push(buf);
+        }
+        for (i, buf) in buffers.iter().enumerate() {
+            assert!(buf.iter().all(|&b| b == i as u8));
+        }
+        drop(buffers);
+
+        // sizes are rounded up to the pool alignment
+        assert_eq!(pool.alloc::<u8>(&mut ap, 12).unwrap().len(), 12);
+        assert!(pool.alloc::<u64>(&mut ap, 0).unwrap().is_empty());
+
+        let other = MvtPool::new(pool.pool.arena().clone()).unwrap();
+        assert!(other.alloc::<u8>(&mut ap, 64).is_err());
+    }
+
+    #[test]
+    fn mvt_alloc_checks_pool_alignment() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvtPool::builder().align(32).build(arena).unwrap();
+        let mut ap = pool.ap().unwrap();
+        assert_eq!(ap.pool().align(), 32);
+
+        // aligned to the platform, but not to the pool
+        assert!(unsafe { ap.alloc(16, |_| ()) }.is_err());
+        let chunk = pool.alloc::<u64>(&mut ap, 2).unwrap();
+        assert_eq!(chunk.as_ptr() as usize % 32, 0);
+    }
+
+    #[test]
+    fn mvt_invalid_params() {
+        let arena: ArenaRef = VmArena::with_capacity(64 << 20).unwrap().into();
+        assert!(MvtPool::builder().min_size(128).mean_size(64).build(arena.clone()).is_err());
+        assert!(MvtPool::builder().max_size(16).build(arena.clone()).is_err());
+        assert!(MvtPool::builder().reserve_depth(0).build(arena.clone()).is_err());
+        assert!(MvtPool::builder().frag_limit(1.5).build(arena).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Segregated allocation caches for manual pools
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::os::raw;
+use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
+
+use errors::{Error, Result};
+use ffi::{mps_addr_t, mps_sac_class_s, mps_sac_create, mps_sac_destroy, mps_sac_empty, mps_sac_fill,
+          mps_sac_flush, mps_sac_t};
+use pool::ManualAllocPool;
+
+/// Maximal number of size classes of a cache (`MPS_SAC_CLASS_LIMIT`)
+pub const CLASS_LIMIT: usize = 8;
+
+/// A size class of a segregated allocation cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClass {
+    /// Largest block size served from this class, a multiple of the pool
+    /// alignment
+    pub block_size: usize,
+    /// Number of free blocks kept in the cache
+    pub cached_count: usize,
+    /// Relative frequency of allocations in this class
+    pub frequency: u32,
+}
+
+/// A cache of free blocks in front of a manual pool.
+///
+/// Requests are rounded up to the smallest size class which fits them and
+/// served from the blocks of that class cached in the cache, only going to
+/// the pool when the class is empty. Freed blocks go back to the cache until
+/// the class is full. Requests larger than the largest class go straight to
+/// the pool.
+///
+/// The cache is not thread safe, but can be sent to another thread if the
+/// pool can be shared between threads. It is destroyed before its pool, any
+/// cached blocks are returned to the pool.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/cache.html)
+/// for details.
+pub struct SegregatedCache<'pool, P: ManualAllocPool + 'pool> {
+    sac: mps_sac_t,
+    pool: &'pool P,
+    // the cache is modified through shared references
+    _marker: PhantomData<*mut ()>,
+}
+
+impl<'pool, P: ManualAllocPool> SegregatedCache<'pool, P> {
+    /// Creates a cache with the given size classes, ordered by increasing
+    /// block size.
+    pub fn new(pool: &'pool P, classes: &[SizeClass]) -> Result<Self> {
+        if classes.is_empty() || classes.len() > CLASS_LIMIT {
+            return Err(Error::InvalidArgument("invalid number of cache size classes"));
+        }
+
+        let mut raw_classes = Vec::with_capacity(classes.len());
+        let mut prev_size = mem::size_of::<mps_addr_t>() - 1;
+        let mut total_frequency: raw::c_uint = 0;
+        for class in classes {
+            if class.block_size <= prev_size {
+                return Err(Error::InvalidArgument("cache size classes must be increasing and hold a pointer"));
+            }
+            if class.block_size % pool.align() != 0 {
+                return Err(Error::InvalidArgument("cache block size is not aligned to the pool"));
+            }
+            pool.check_size(class.block_size)?;
+            total_frequency = total_frequency
+                .checked_add(class.frequency)
+                .ok_or(Error::InvalidArgument("cache class frequencies overflow"))?;
+            prev_size = class.block_size;
+
+            raw_classes.push(mps_sac_class_s {
+                mps_block_size: class.block_size,
+                mps_cached_count: class.cached_count,
+                mps_frequency: class.frequency,
+            });
+        }
+        // the MPS picks its middle class by frequency
+        if total_frequency == 0 {
+            return Err(Error::InvalidArgument("cache class frequencies must not all be zero"));
+        }
+
+        let mut sac: mps_sac_t = ptr::null_mut();
+        unsafe {
+            Error::result(mps_sac_create(&mut sac, pool.as_raw(), raw_classes.len(), raw_classes.as_mut_ptr()))?;
+        }
+
+        Ok(SegregatedCache {
+            sac: sac,
+            pool: pool,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Return the raw cache pointer
+    pub fn as_raw(&self) -> mps_sac_t {
+        self.sac
+    }
+
+    /// Access the pool this cache allocates from
+    pub fn pool(&self) -> &'pool P {
+        self.pool
+    }
+
+    /// Allocates a block of at least `size` bytes
+    #[inline]
+    pub fn alloc(&self, size: usize) -> Result<mps_addr_t> {
+        if size == 0 {
+            return Err(Error::InvalidArgument("cannot allocate an empty block from a cache"));
+        }
+        self.pool.check_size(size)?;
+
+        // the inline part of MPS_SAC_ALLOC_FAST
+        unsafe {
+            let list = &mut (*self.sac)._freelists[self.class_index(size)];
+            if list._count != 0 {
+                let addr = list._blocks;
+                list._blocks = *(addr as *mut mps_addr_t);
+                list._count -= 1;
+                return Ok(addr);
+            }
+
+            let mut addr: mps_addr_t = ptr::null_mut();
+            Error::result(mps_sac_fill(&mut addr, self.sac, size, 0))?;
+            Ok(addr)
+        }
+    }
+
+    /// Frees a block, returning it to the cache or to the pool if its size
+    /// class is full.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must have been allocated from this cache with the same `size`,
+    /// and must not be used afterwards.
+    #[inline]
+    pub unsafe fn free(&self, addr: mps_addr_t, size: usize) {
+        // the inline part of MPS_SAC_FREE_FAST
+        let list = &mut (*self.sac)._freelists[self.class_index(size)];
+        if list._count < list._count_max {
+            *(addr as *mut mps_addr_t) = list._blocks;
+            list._blocks = addr;
+            list._count += 1;
+        } else {
+            mps_sac_empty(self.sac, addr, size)
+        }
+    }
+
+    /// Returns all cached blocks to the pool
+    pub fn flush(&self) {
+        unsafe { mps_sac_flush(self.sac) }
+    }
+
+    /// Moves `value` into a block allocated from the cache. The size class
+    /// is chosen from the size of `T`, zero-sized values do not allocate.
+    pub fn alloc_value<'cache, T>(&'cache self, value: T) -> Result<CacheBox<'cache, T, P>> {
+        if mem::align_of::<T>() > self.pool.align() {
+            return Err(Error::InvalidArgument("type alignment exceeds the pool alignment"));
+        }
+
+        let size = CacheBox::<T, P>::block_size();
+        let ptr = if size == 0 {
+            ptr::NonNull::dangling().as_ptr()
+        } else {
+            self.alloc(size)? as *mut T
+        };
+        unsafe { ptr::write(ptr, value) };
+
+        Ok(CacheBox {
+            cache: self,
+            ptr: ptr,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Index of the free list serving blocks of `size` bytes, the same
+    /// search as the MPS cache macros
+    #[inline]
+    fn class_index(&self, size: usize) -> usize {
+        let sac = unsafe { &*self.sac };
+        if size > sac._middle {
+            let mut i = 0;
+            while size > sac._freelists[i]._size {
+                i += 2;
+            }
+            i
+        } else {
+            let mut i = 1;
+            while size <= sac._freelists[i]._size {
+                i += 2;
+            }
+            i
+        }
+    }
+}
+
+impl<'pool, P: ManualAllocPool> Drop for SegregatedCache<'pool, P> {
+    fn drop(&mut self) {
+        unsafe { mps_sac_destroy(self.sac) }
+    }
+}
+
+unsafe impl<'pool, P: ManualAllocPool + Sync> Send for SegregatedCache<'pool, P> {}
+
+/// A value allocated from a segregated allocation cache.
+///
+/// The value is dropped and its block returned to the cache on drop.
+pub struct CacheBox<'cache, T, P: ManualAllocPool + 'cache> {
+    cache: &'cache SegregatedCache<'cache, P>,
+    ptr: *mut T,
+    _marker: PhantomData<T>,
+}
+
+impl<'cache, T, P: ManualAllocPool> CacheBox<'cache, T, P> {
+    /// Size of the block holding a `T`, a pointer at least so it can be
+    /// linked into the free lists of the cache
+    fn block_size() -> usize {
+        match mem::size_of::<T>() {
+            0 => 0,
+            size => size.max(mem::size_of::<mps_addr_t>()),
+        }
+    }
+
+    /// Moves the value out of the box, returning its block to the cache
+    pub fn into_inner(self) -> T {
+        unsafe {
+            let value = ptr::read(self.ptr);
+            self.release();
+            mem::forget(self);
+            value
+        }
+    }
+
+    unsafe fn release(&self) {
+        let size = Self::block_size();
+        if size != 0 {
+            self.cache.free(self.ptr as mps_addr_t, size)
+        }
+    }
+}
+
+impl<'cache, T, P: ManualAllocPool> Drop for CacheBox<'cache, T, P> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr);
+            self.release();
+        }
+    }
+}
+
+impl<'cache, T, P: ManualAllocPool> Deref for CacheBox<'cache, T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'cache, T, P: ManualAllocPool> DerefMut for CacheBox<'cache, T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<'cache, T: fmt::Debug, P: ManualAllocPool> fmt::Debug for CacheBox<'cache, T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use pool::mvff::MvffPool;
+
+    const CLASSES: &[SizeClass] = &[
+        SizeClass { block_size: 16, cached_count: 64, frequency: 4 },
+        SizeClass { block_size: 32, cached_count: 32, frequency: 2 },
+        SizeClass { block_size: 128, cached_count: 8, frequency: 1 },
+    ];
+
+    #[test]
+    fn sac_reuses_blocks() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let cache = SegregatedCache::new(&pool, CLASSES).unwrap();
+
+        let addr = cache.alloc(24).unwrap();
+        unsafe { cache.free(addr, 24) };
+        assert_eq!(cache.alloc(32).unwrap(), addr);
+
+        // larger than every class, served by the pool
+        let large = cache.alloc(4096).unwrap();
+        unsafe {
+            cache.free(large, 4096);
+            cache.free(addr, 32);
+        }
+        cache.flush();
+        assert!(cache.alloc(0).is_err());
+    }
+
+    #[test]
+    fn sac_free_beyond_cached_count() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let cache = SegregatedCache::new(&pool, CLASSES).unwrap();
+
+        let blocks: Vec<_> = (0..100).map(|_| cache.alloc(128).unwrap()).collect();
+        for &addr in &blocks {
+            unsafe { cache.free(addr, 128) };
+        }
+        assert!(blocks.contains(&cache.alloc(128).unwrap()));
+    }
+
+    #[test]
+    fn sac_alloc_value() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let cache = SegregatedCache::new(&pool, CLASSES).unwrap();
+        let value = Rc::new(42);
+        {
+            let boxed = cache.alloc_value((value.clone(), [0u8; 20])).unwrap();
+            assert_eq!(Rc::strong_count(&value), 2);
+            assert_eq!(*boxed.0, 42);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let boxed = cache.alloc_value(7u8).unwrap();
+        assert_eq!(boxed.into_inner(), 7);
+        assert_eq!(*cache.alloc_value(()).unwrap(), ());
+    }
+
+    #[test]
+    fn sac_invalid_classes() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        assert!(SegregatedCache::new(&pool, &[]).is_err());
+        assert!(SegregatedCache::new(&pool, &[CLASSES[1], CLASSES[0]]).is_err());
+
+        let unaligned = SizeClass { block_size: 20, cached_count: 1, frequency: 1 };
+        assert!(SegregatedCache::new(&pool, &[unaligned]).is_err());
+    }
+
+    #[test]
+    fn sac_zero_frequencies() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let classes: Vec<_> = CLASSES.iter().map(|&class| SizeClass { frequency: 0, ..class }).collect();
+        assert!(SegregatedCache::new(&pool, &classes).is_err());
+    }
+}
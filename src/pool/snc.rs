@@ -0,0 +1,91 @@
+//! Stack No Checking pool
+
+use ap::AllocationPoint;
+use errors::{Error, Result};
+use fmt::FormatRef;
+use ffi::{mps_class_snc, mps_pool_t};
+use pool::{FormattedPool, Pool, PoolRef};
+use root::Rank;
+
+/// A manually managed pool for objects allocated and freed in stack order,
+/// such as the control stack of an interpreter.
+///
+/// Objects are scanned, but never collected. They are only freed by
+/// popping the allocation frame they were allocated in, see
+/// `AllocationPoint::push_frame`.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/pool/snc.html)
+/// for details.
+pub struct SncPool {
+    pool: PoolRef,
+}
+
+impl SncPool {
+    /// Creates a new pool for objects of the given format
+    pub fn new<F: Into<FormatRef>>(fmt: F) -> Result<Self> {
+        let class = unsafe { mps_class_snc() };
+        FormattedPool::create(class, fmt.into(), None, None, Vec::new()).map(|pool| SncPool { pool })
+    }
+
+    /// Creates a new allocation point. Objects in this pool can only
+    /// contain exact references, any other rank is rejected.
+    pub fn ap(&self, rank: Rank) -> Result<AllocationPoint> {
+        if rank != Rank::Exact {
+            return Err(Error::InvalidArgument("SNC allocation points only support exact references"));
+        }
+
+        AllocationPoint::new(self.pool.clone(), Some(rank))
+    }
+}
+
+impl Pool for SncPool {
+    fn as_raw(&self) -> mps_pool_t {
+        self.pool.as_raw()
+    }
+}
+
+impl Into<PoolRef> for SncPool {
+    fn into(self) -> PoolRef {
+        self.pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use ffi::mps_addr_t;
+    use fmt::area::{alloc_leaf, AreaFormat};
+    use test_util::Untagged;
+
+    unsafe fn payload<'a>(addr: mps_addr_t, len: usize) -> &'a [u8] {
+        slice::from_raw_parts((addr as *const u64).offset(1) as *const u8, len)
+    }
+
+    #[test]
+    fn snc_nested_frames() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let fmt = AreaFormat::tagged::<Untagged, _>(arena).unwrap();
+        let pool = SncPool::new(fmt).unwrap();
+        assert!(pool.ap(Rank::Weak).is_err());
+
+        let mut ap = pool.ap(Rank::Exact).unwrap();
+        let bottom = unsafe { alloc_leaf(&mut ap, 0, b"bottom").unwrap() };
+        {
+            let mut outer = ap.push_frame().unwrap();
+            unsafe { alloc_leaf(&mut outer, 0, b"outer").unwrap() };
+            {
+                let mut inner = outer.push_frame().unwrap();
+                unsafe { alloc_leaf(&mut inner, 0, b"inner").unwrap() };
+            }
+            unsafe { alloc_leaf(&mut outer, 0, b"outer again").unwrap() };
+            outer.pop_frame().unwrap();
+        }
+
+        let top = unsafe { alloc_leaf(&mut ap, 0, b"top").unwrap() };
+        assert_eq!(unsafe { payload(bottom, 6) }, b"bottom");
+        assert_eq!(unsafe { payload(top, 3) }, b"top");
+    }
+}
@@ -0,0 +1,258 @@
+//! Growable arrays allocated in manual pools
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
+
+use errors::{Error, Result};
+use pool::{alloc_array, free_array, ManualAllocPool};
+
+/// Minimal capacity of a non-empty vector
+const MIN_CAPACITY: usize = 4;
+
+/// A growable array allocated in a manually managed pool.
+///
+/// Growing the vector allocates a new block and moves the elements over,
+/// every operation which might allocate is fallible. Vectors of zero-sized
+/// values never allocate. The vector can be sent to other threads if its
+/// elements can, and the pool can be shared between threads.
+pub struct PoolVec<'pool, T, P: ManualAllocPool + 'pool> {
+    pool: &'pool P,
+    ptr: *mut T,
+    cap: usize,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<'pool, T, P: ManualAllocPool> PoolVec<'pool, T, P> {
+    /// Creates a new, empty vector. This does not allocate.
+    pub fn new(pool: &'pool P) -> Self {
+        PoolVec {
+            pool: pool,
+            ptr: ptr::NonNull::dangling().as_ptr(),
+            cap: if mem::size_of::<T>() == 0 { usize::max_value() } else { 0 },
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a new, empty vector with space for at least `capacity`
+    /// elements
+    pub fn with_capacity(pool: &'pool P, capacity: usize) -> Result<Self> {
+        let mut vec = Self::new(pool);
+        vec.reserve(capacity)?;
+        Ok(vec)
+    }
+
+    /// Number of elements in the vector
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the vector has no elements
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of elements the vector can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Access the pool this vector is allocated in
+    pub fn pool(&self) -> &'pool P {
+        self.pool
+    }
+
+    /// Reserves space for at least `additional` more elements
+    pub fn reserve(&mut self, additional: usize) -> Result<()> {
+        let required = self.len
+            .checked_add(additional)
+            .ok_or(Error::InvalidArgument("vector capacity overflows"))?;
+        if required <= self.cap {
+            return Ok(());
+        }
+
+        let cap = required.max(self.cap.saturating_mul(2)).max(MIN_CAPACITY);
+        self.reallocate(cap)
+    }
+
+    /// Releases unused capacity
+    pub fn shrink_to_fit(&mut self) -> Result<()> {
+        if mem::size_of::<T>() == 0 || self.cap == self.len {
+            return Ok(());
+        }
+
+        let len = self.len;
+        self.reallocate(len)
+    }
+
+    /// Appends `value` to the end of the vector
+    pub fn push(&mut self, value: T) -> Result<()> {
+        if self.len == self.cap {
+            self.reserve(1)?;
+        }
+
+        unsafe { ptr::write(self.ptr.offset(self.len as isize), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last element, returns `None` if the vector is empty
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        unsafe { Some(ptr::read(self.ptr.offset(self.len as isize))) }
+    }
+
+    /// Drops all elements after the first `len` ones, keeping the capacity
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    /// Drops all elements, keeping the capacity
+    pub fn clear(&mut self) {
+        self.truncate(0)
+    }
+
+    /// Moves the elements into a block of `cap` elements
+    fn reallocate(&mut self, cap: usize) -> Result<()> {
+        unsafe {
+            let ptr = alloc_array(self.pool, cap)?;
+            ptr::copy_nonoverlapping(self.ptr, ptr, self.len);
+            free_array(self.pool, self.ptr, self.cap);
+            self.ptr = ptr;
+        }
+
+        self.cap = cap;
+        Ok(())
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> Drop for PoolVec<'pool, T, P> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut self[..]);
+            free_array(self.pool, self.ptr, self.cap);
+        }
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> Deref for PoolVec<'pool, T, P> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<'pool, T, P: ManualAllocPool> DerefMut for PoolVec<'pool, T, P> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<'pool, T: fmt::Debug, P: ManualAllocPool> fmt::Debug for PoolVec<'pool, T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl<'pool, T: Send, P: ManualAllocPool + Sync> Send for PoolVec<'pool, T, P> {}
+unsafe impl<'pool, T: Sync, P: ManualAllocPool + Sync> Sync for PoolVec<'pool, T, P> {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use arena::vm::VmArena;
+    use pool::Pool;
+    use pool::mfs::MfsPool;
+    use pool::mvff::MvffPool;
+
+    #[test]
+    fn pool_vec_push_pop() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let mut vec = PoolVec::new(&pool);
+        assert_eq!(vec.capacity(), 0);
+
+        for i in 0..1000u64 {
+            vec.push(i).unwrap();
+        }
+        assert_eq!(vec.len(), 1000);
+        assert_eq!(vec.iter().sum::<u64>(), 999 * 1000 / 2);
+        assert_eq!(vec.pop(), Some(999));
+
+        vec.shrink_to_fit().unwrap();
+        assert_eq!(vec.capacity(), 999);
+        assert_eq!(vec[998], 998);
+    }
+
+    #[test]
+    fn pool_vec_drop_elements() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let value = Rc::new(42);
+        {
+            let mut vec = PoolVec::with_capacity(&pool, 10).unwrap();
+            assert!(vec.capacity() >= 10);
+            for _ in 0..100 {
+                vec.push(value.clone()).unwrap();
+            }
+            assert_eq!(Rc::strong_count(&value), 101);
+            vec.truncate(50);
+            assert_eq!(Rc::strong_count(&value), 51);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn pool_vec_zero_sized() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let total = pool.total_size();
+        let mut vec = PoolVec::new(&pool);
+        for _ in 0..1000 {
+            vec.push(()).unwrap();
+        }
+        assert_eq!(vec.len(), 1000);
+        assert_eq!(pool.total_size(), total);
+    }
+
+    #[test]
+    fn pool_vec_overflow() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MvffPool::new(arena).unwrap();
+        let mut vec = PoolVec::<u64, _>::new(&pool);
+        assert!(vec.reserve(usize::max_value() / 4).is_err());
+        assert!(vec.reserve(usize::max_value()).is_err());
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn pool_vec_mfs_size() {
+        let arena = VmArena::with_capacity(64 << 20).unwrap();
+        let pool = MfsPool::new(arena, 4 * mem::size_of::<u64>()).unwrap();
+        let mut vec = PoolVec::new(&pool);
+        for i in 0..4u64 {
+            vec.push(i).unwrap();
+        }
+        assert!(vec.push(4).is_err());
+    }
+
+    #[test]
+    fn pool_vec_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<PoolVec<'static, u64, MvffPool>>();
+    }
+}
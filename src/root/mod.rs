@@ -2,7 +2,36 @@
 
 pub mod area;
 
-use ffi::{mps_root_t, mps_root_destroy};
+use ffi::{mps_rank_ambig, mps_rank_exact, mps_rank_t, mps_rank_weak, mps_root_t, mps_root_destroy};
+
+/// Rank of the references in a root or in the objects of an allocation point.
+///
+/// See [the reference](https://www.ravenbrook.com/project/mps/master/manual/html/topic/root.html#ranks)
+/// for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rank {
+    /// References which may or may not be valid, objects they point to are
+    /// neither moved nor collected.
+    Ambiguous,
+    /// Valid references keeping their referent alive.
+    Exact,
+    /// Valid references which do not keep their referent alive, they are
+    /// set to zero when it dies.
+    Weak,
+}
+
+impl Rank {
+    /// Returns the raw MPS rank
+    pub fn as_raw(&self) -> mps_rank_t {
+        unsafe {
+            match *self {
+                Rank::Ambiguous => mps_rank_ambig(),
+                Rank::Exact => mps_rank_exact(),
+                Rank::Weak => mps_rank_weak(),
+            }
+        }
+    }
+}
 
 /// Generic root interface
 pub trait Root {
@@ -0,0 +1,18 @@
+//! Fixtures shared by the unit tests
+
+use chain::GenParams;
+use fmt::area::ReferenceTag;
+
+/// Tag for untagged, eight byte aligned references
+pub struct Untagged;
+
+impl ReferenceTag for Untagged {
+    const MASK: u64 = 0b111;
+    const PATTERN: u64 = 0b000;
+}
+
+/// A two generation chain
+pub const GENS: &[GenParams] = &[
+    GenParams { capacity_kb: 1024, mortality: 0.8 },
+    GenParams { capacity_kb: 4096, mortality: 0.4 },
+];